@@ -1,8 +1,246 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[path = "src/kernel_order.rs"]
+mod kernel_order;
+
+const SHADERS: &str = "src/shaders";
+
+fn files_with_extension(dir: &Path,extension: &str) -> Vec<String> {
+    let mut stems = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .map(|path| path.file_stem().unwrap().to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    stems.sort();
+    stems
+}
+
+struct Kernel {
+    name: String,
+    index: usize,
+    // The .compute declaring it: <root>/<group>.compute, or <root>/<group>/<kernel>.compute for groups without one.
+    source: Option<PathBuf>,
+}
+struct Group {
+    root: &'static str,
+    name: String,
+    kernels: Vec<Kernel>,
+}
+
+// Every group directory under src/shaders/<root>/ with .spv or .compute files, indexed as kernel_order.rs describes.
+fn scan_groups() -> Vec<Group> {
+    let mut groups = Vec::new();
+    for root in ["main","Utility"] {
+        let root_dir = Path::new(SHADERS).join(root);
+        let mut dirs = fs::read_dir(&root_dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_dir())
+            .collect::<Vec<_>>();
+        dirs.sort();
+        for dir in dirs {
+            let name = dir.file_name().unwrap().to_str().unwrap().to_string();
+            let group_source = root_dir.join(format!("{}.compute",name));
+            let declared_kernels = fs::read_to_string(&group_source).map(|source| kernel_order::pragma_kernels(&source)).unwrap_or_default();
+            let declared = declared_kernels.len();
+            let per_kernel_sources = files_with_extension(&dir,"compute");
+            let order = kernel_order::kernel_order(declared_kernels,files_with_extension(&dir,"spv").into_iter().chain(per_kernel_sources.iter().cloned()));
+            if order.is_empty() {
+                continue;
+            }
+            let kernels = order.into_iter().enumerate().map(|(index,kernel)| {
+                let source = if index < declared {
+                    Some(group_source.clone())
+                } else if per_kernel_sources.contains(&kernel) {
+                    Some(dir.join(format!("{}.compute",kernel)))
+                } else {
+                    None
+                };
+                Kernel { name: kernel, index, source }
+            }).collect();
+            groups.push(Group { root, name, kernels });
+        }
+    }
+    groups
+}
+
+// Runs DXC on one kernel with the same include paths Unity resolves. The sources pull in GlobalDefines.cginc for the
+// define set; TRUETRACE_SHADER_DEFINES (`A;B=1`) adds to it. `shaders` is src/shaders or a copy of it.
+fn compile_kernel(dxc: &Path,shaders: &Path,source: &Path,kernel: &str,output: &Path) {
+    let mut command = Command::new(dxc);
+    command.args(["-spirv","-T","cs_6_6","-HV","2021","-fspv-target-env=vulkan1.2","-E",kernel])
+        .arg("-I").arg(shaders)
+        .arg("-I").arg(shaders.join("main"))
+        .arg("-I").arg(shaders.join("Utility"))
+        .arg("-I").arg(shaders.join("Utility/External/AMD_Compressonator"));
+    if let Ok(defines) = std::env::var("TRUETRACE_SHADER_DEFINES") {
+        for define in defines.split(';').map(str::trim).filter(|define| !define.is_empty()) {
+            command.arg("-D").arg(define);
+        }
+    }
+    command.arg("-Fo").arg(output).arg(source);
+    let result = command.output().unwrap_or_else(|err| panic!("could not run {}: {}",dxc.display(),err));
+    if !result.status.success() {
+        panic!("DXC failed on {} ({}):\n{}",kernel,source.display(),String::from_utf8_lossy(&result.stderr));
+    }
+}
+
+// $DXC if set, otherwise `dxc` on PATH. Only the `compile-shaders` feature runs it.
+fn find_dxc() -> PathBuf {
+    println!("cargo:rerun-if-env-changed=DXC");
+    let dxc = std::env::var_os("DXC").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("dxc"));
+    match Command::new(&dxc).arg("--version").output() {
+        Ok(_) => dxc,
+        Err(err) => panic!("the compile-shaders feature needs the DirectX Shader Compiler, but {} could not be run ({}). \
+            Install DXC or point the DXC environment variable at it.",dxc.display(),err),
+    }
+}
+
+// Same walk as render::uses_ray_query: is there an `OpCapability RayQueryKHR`?
+fn uses_ray_query(spirv: &[u8]) -> bool {
+    let words = spirv.chunks_exact(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect::<Vec<_>>();
+    let mut at = 5;
+    while at < words.len() {
+        let count = (words[at] >> 16) as usize;
+        if words[at] & 0xffff == 17 && words.get(at + 1) == Some(&4472) {
+            return true;
+        }
+        if count == 0 {
+            break;
+        }
+        at += count;
+    }
+    false
+}
+
+struct Embedded {
+    group: String,
+    kernel: String,
+    path: String,
+    file: PathBuf,
+    source: Option<PathBuf>,
+}
+
+// Writes the embedded kernel table. With `compile-shaders` every kernel with a source is compiled into OUT_DIR;
+// otherwise, and for kernels without a source, the committed .spv is used.
+fn write_kernel_manifest(out: &Path,dxc: Option<&Path>) -> Vec<Embedded> {
+    println!("cargo:rerun-if-env-changed=TRUETRACE_SHADER_DEFINES");
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let mut embedded = Vec::new();
+    let mut entries = String::from("// embedded!(group, kernel, index, path, file). `index` is 0-based: the kernel's position among the\n\
+        // `#pragma kernel` lines of <group>.compute, i.e. Unity's FindKernel index; kernels it doesn't declare follow, by name.\n&[\n");
+    for group in scan_groups() {
+        for kernel in group.kernels {
+            let path = format!("{}/{}/{}.spv",group.root,group.name,kernel.name);
+            let file = match (dxc,&kernel.source) {
+                (Some(dxc),Some(source)) => {
+                    let output = out.join("shaders").join(&path);
+                    fs::create_dir_all(output.parent().unwrap()).unwrap();
+                    compile_kernel(dxc,Path::new(SHADERS),source,&kernel.name,&output);
+                    output
+                },
+                _ => manifest_dir.join(SHADERS).join(&path),
+            };
+            if !file.exists() {
+                println!("cargo:warning={}/{} is declared but has no compiled .spv, index {} stays unused",group.name,kernel.name,kernel.index);
+                continue;
+            }
+            entries.push_str(&format!("    embedded!({:?},{:?},{},{:?},{:?}),\n",group.name,kernel.name,kernel.index,path,file.to_str().unwrap()));
+            embedded.push(Embedded { group: group.name.clone(), kernel: kernel.name, path, file, source: kernel.source });
+        }
+    }
+    entries.push_str("]\n");
+    fs::write(out.join("embedded_kernels.rs"),entries).unwrap();
+    embedded
+}
+
+// Copies src/shaders with `HardwareRT` switched off in GlobalDefines.cginc, the same edit Unity makes when hardware
+// RT is unchecked. The sources include it by relative path, so a -D can't override it.
+fn software_shader_tree(out: &Path) -> PathBuf {
+    fn copy(from: &Path,to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy(&path,&target);
+            } else if path.extension().is_none_or(|ext| ext != "spv") {
+                fs::copy(&path,&target).unwrap();
+            }
+        }
+    }
+    let tree = out.join("software_src");
+    copy(Path::new(SHADERS),&tree);
+    let defines = tree.join("GlobalDefines.cginc");
+    let text = fs::read_to_string(&defines).unwrap();
+    let text = text.lines()
+        .map(|line| if line.trim() == "#define HardwareRT" { "// #define HardwareRT" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+    fs::write(&defines,text).unwrap();
+    tree
+}
+
+// Software BVH builds of every embedded kernel that uses ray query, for adapters without it. With `compile-shaders`
+// they are compiled with DXC; otherwise the checked-in src/shaders/<root>/<group>/software/<kernel>.spv is embedded.
+fn write_software_kernels(out: &Path,dxc: Option<&Path>,embedded: &[Embedded]) {
+    let needs_software = embedded.iter().filter(|kernel| uses_ray_query(&fs::read(&kernel.file).unwrap())).collect::<Vec<_>>();
+    let tree = (dxc.is_some() && !needs_software.is_empty()).then(|| software_shader_tree(out));
+    let mut entries = String::from("&[\n");
+    for kernel in needs_software {
+        let checked_in = Path::new(SHADERS).join(&kernel.path).with_file_name("software").join(format!("{}.spv",kernel.kernel));
+        let file = match (dxc,&tree,&kernel.source) {
+            (Some(dxc),Some(tree),Some(source)) => {
+                let output = out.join("software").join(&kernel.path);
+                fs::create_dir_all(output.parent().unwrap()).unwrap();
+                compile_kernel(dxc,tree,&tree.join(source.strip_prefix(SHADERS).unwrap()),&kernel.kernel,&output);
+                output
+            },
+            _ if checked_in.exists() => fs::canonicalize(&checked_in).unwrap(),
+            _ => {
+                println!("cargo:warning=no software BVH build of {}/{} (no {}), it won't run without ray query",kernel.group,kernel.kernel,checked_in.display());
+                continue;
+            }
+        };
+        if uses_ray_query(&fs::read(&file).unwrap()) {
+            panic!("the software BVH build of {}/{} still uses ray query",kernel.group,kernel.kernel);
+        }
+        entries.push_str(&format!("    software!({:?},{:?},{:?}),\n",kernel.group,kernel.kernel,file.to_str().unwrap()));
+    }
+    entries.push_str("]\n");
+    fs::write(out.join("software_kernels.rs"),entries).unwrap();
+}
+
+// Exposed as TRUETRACE_SPIRV_CROSS_VERSION so the shader cache is invalidated when the translator changes.
+fn spirv_cross_version() -> String {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let mut lines = lock.lines().skip_while(|line| *line != "name = \"spirv-cross2\"");
+    lines.nth(1)
+        .and_then(|line| line.strip_prefix("version = \""))
+        .map(|version| version.trim_end_matches('"').to_string())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+const EXTERN_FILES: &[&str] = &["src/compute_shader_interop.rs","src/render.rs","src/standalone.rs","src/logging.rs","src/device.rs","src/memory.rs"];
+
 fn main() {
-    csbindgen::Builder::default()
-        .input_extern_file("src/compute_shader_interop.rs")
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/shaders");
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let dxc = std::env::var_os("CARGO_FEATURE_COMPILE_SHADERS").map(|_| find_dxc());
+    let embedded = write_kernel_manifest(&out,dxc.as_deref());
+    write_software_kernels(&out,dxc.as_deref(),&embedded);
+    println!("cargo:rustc-env=TRUETRACE_SPIRV_CROSS_VERSION={}",spirv_cross_version());
+    let mut builder = csbindgen::Builder::default();
+    for file in EXTERN_FILES {
+        println!("cargo:rerun-if-changed={}",file);
+        builder = builder.input_extern_file(*file);
+    }
+    builder
         .csharp_dll_name("version")
         .csharp_class_name("TrueTraceNative")
         .generate_csharp_file("./dotnet/NativeMethods.g.cs")
         .unwrap();
-}
\ No newline at end of file
+}
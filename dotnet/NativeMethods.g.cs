@@ -14,14 +14,51 @@ namespace CsBindgen
     {
         const string __DllName = "version";
 
+        internal const uint KERNEL_NOT_FOUND = 4294967295;
+        internal const uint INIT_NOT_STARTED = 0;
+        internal const uint INIT_RUNNING = 1;
+        internal const uint INIT_DONE = 2;
+        internal const uint INIT_FAILED = 3;
+        internal const uint BACKEND_DX12 = 1;
+        internal const uint BACKEND_VULKAN = 2;
+        internal const uint BACKEND_GL = 4;
+        internal const uint DEVICE_STATUS_UNINITIALISED = 0;
+        internal const uint DEVICE_STATUS_READY = 1;
+        internal const uint DEVICE_STATUS_LOST = 2;
+        internal const uint DEVICE_STATUS_RECOVERING = 3;
+        internal const uint CAPABILITY_RAY_QUERY = 1;
+        internal const uint CAPABILITY_PASSTHROUGH_SHADERS = 2;
+        internal const uint CAPABILITY_SOFTWARE_BVH = 4;
+        internal const uint MEMORY_BVH = 0;
+        internal const uint MEMORY_TRIANGLES = 1;
+        internal const uint MEMORY_ATLASES = 2;
+        internal const uint MEMORY_RESERVOIRS = 3;
+        internal const uint MEMORY_RAYS = 4;
+        internal const uint MEMORY_TEXTURES = 5;
+        internal const uint MEMORY_GLOBALS = 6;
+        internal const uint MEMORY_OTHER = 7;
+        internal const nuint MEMORY_CATEGORY_COUNT = 8;
+        internal const uint MEMORY_LEVEL_NORMAL = 0;
+        internal const uint MEMORY_LEVEL_WARNING = 1;
+        internal const uint MEMORY_LEVEL_CRITICAL = 2;
 
 
         [DllImport(__DllName, EntryPoint = "ComputeShader_Load", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern ComputeShader* ComputeShader_Load(byte* shader);
 
+        [DllImport(__DllName, EntryPoint = "ComputeShader_LoadFromSpirv", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern ComputeShader* ComputeShader_LoadFromSpirv(byte* name, uint kernel_count, byte** kernels, byte** spirv, nuint* spirv_sizes);
+
+        [DllImport(__DllName, EntryPoint = "ComputeShader_Release", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void ComputeShader_Release(ComputeShader* _self);
+
         [DllImport(__DllName, EntryPoint = "ComputeShader_Dispatch", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern void ComputeShader_Dispatch(ComputeShader* _self, uint kernel_index, uint x, uint y, uint z);
 
+        [DllImport(__DllName, EntryPoint = "ComputeShader_Prewarm", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool ComputeShader_Prewarm(ComputeShader* _self, uint* kernel_indices, uint kernel_count);
+
         [DllImport(__DllName, EntryPoint = "ComputeShader_DispatchIndirect", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern void ComputeShader_DispatchIndirect(ComputeShader* _self, uint kernel_index, void* buf, nuint buf_size);
 
@@ -49,6 +86,76 @@ namespace CsBindgen
         [DllImport(__DllName, EntryPoint = "ComputeShader_SetVector", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
         internal static extern void ComputeShader_SetVector(ComputeShader* _self, byte* name, byte* val);
 
+        [DllImport(__DllName, EntryPoint = "ComputeShader_SetOwnedBuffer", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void ComputeShader_SetOwnedBuffer(ComputeShader* _self, uint kernel_index, byte* name, OwnedBuffer* buffer);
+
+        [DllImport(__DllName, EntryPoint = "ComputeShader_SetOwnedTexture", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void ComputeShader_SetOwnedTexture(ComputeShader* _self, uint kernel_index, byte* name, OwnedTexture* texture);
+
+        [DllImport(__DllName, EntryPoint = "init", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void init();
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_GetInitProgress", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern uint TrueTrace_GetInitProgress(uint* done, uint* total);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_InitStandalone", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool TrueTrace_InitStandalone(StandaloneOptions* options);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_CreateBuffer", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern OwnedBuffer* TrueTrace_CreateBuffer(byte* label, nuint size, byte* contents);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_WriteBuffer", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool TrueTrace_WriteBuffer(OwnedBuffer* buffer, nuint offset, byte* data, nuint len);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_ReadBuffer", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool TrueTrace_ReadBuffer(OwnedBuffer* buffer, nuint offset, byte* @out, nuint len);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_ReleaseBuffer", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void TrueTrace_ReleaseBuffer(OwnedBuffer* buffer);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_CreateTexture", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern OwnedTexture* TrueTrace_CreateTexture(byte* label, uint width, uint height, uint depth, uint format, int dimension, uint mip_count);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_WriteTexture", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool TrueTrace_WriteTexture(OwnedTexture* texture, uint mip, byte* data, nuint len);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_ReleaseTexture", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void TrueTrace_ReleaseTexture(OwnedTexture* texture);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_SetLogFilter", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool TrueTrace_SetLogFilter(byte* filter);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_SetLogCallback", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void TrueTrace_SetLogCallback(delegate* unmanaged[Cdecl]<int, byte*, void> callback);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_GetDeviceStatus", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern uint TrueTrace_GetDeviceStatus();
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_GetCapabilities", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern uint TrueTrace_GetCapabilities();
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_SetDeviceStatusCallback", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void TrueTrace_SetDeviceStatusCallback(delegate* unmanaged[Cdecl]<uint, byte*, void> callback);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_Shutdown", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void TrueTrace_Shutdown();
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_RecoverDevice", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool TrueTrace_RecoverDevice();
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_GetMemoryStats", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        [return: MarshalAs(UnmanagedType.U1)]
+        internal static extern bool TrueTrace_GetMemoryStats(MemoryStats* stats_out);
+
+        [DllImport(__DllName, EntryPoint = "TrueTrace_SetMemoryCallback", CallingConvention = CallingConvention.Cdecl, ExactSpelling = true)]
+        internal static extern void TrueTrace_SetMemoryCallback(delegate* unmanaged[Cdecl]<uint, MemoryStats*, void> callback);
+
 
     }
 
@@ -57,6 +164,33 @@ namespace CsBindgen
     {
     }
 
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct OwnedBuffer
+    {
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct OwnedTexture
+    {
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct StandaloneOptions
+    {
+        public uint backends;
+        [MarshalAs(UnmanagedType.U1)] public bool high_performance;
+    }
+
+    [StructLayout(LayoutKind.Sequential)]
+    internal unsafe partial struct MemoryStats
+    {
+        public fixed ulong categories[8];
+        public ulong tracked_total;
+        public ulong budget;
+        public ulong usage;
+        public uint level;
+    }
+
 
 
 }
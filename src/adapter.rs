@@ -0,0 +1,152 @@
+use std::fmt;
+use wgpu::{AdapterInfo, DeviceType};
+use crate::config::AdapterPolicy;
+
+#[derive(Debug, Clone)]
+pub struct AdapterCandidate {
+    pub info: AdapterInfo,
+    pub luid: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectionReason {
+    MatchedLuid(u64),
+    Policy(AdapterPolicy),
+    // The configured policy found nothing, so the high-performance ranking was used instead.
+    PolicyFallback(AdapterPolicy),
+}
+impl fmt::Display for SelectionReason {
+    fn fmt(&self,f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectionReason::MatchedLuid(luid) => write!(f,"matches the LUID {:#018x} Unity requested",luid),
+            SelectionReason::Policy(policy) => write!(f,"picked by the {:?} policy",policy),
+            SelectionReason::PolicyFallback(policy) => write!(f,"the {:?} policy matched nothing, fell back to high performance",policy),
+        }
+    }
+}
+
+fn high_performance_rank(device_type: DeviceType) -> u32 {
+    match device_type {
+        DeviceType::DiscreteGpu => 0,
+        DeviceType::IntegratedGpu => 1,
+        DeviceType::VirtualGpu => 2,
+        DeviceType::Other => 3,
+        DeviceType::Cpu => 4,
+    }
+}
+fn low_power_rank(device_type: DeviceType) -> u32 {
+    match device_type {
+        DeviceType::IntegratedGpu => 0,
+        DeviceType::DiscreteGpu => 1,
+        DeviceType::VirtualGpu => 2,
+        DeviceType::Other => 3,
+        DeviceType::Cpu => 4,
+    }
+}
+fn best_by(candidates: &[AdapterCandidate],rank: fn(DeviceType) -> u32) -> Option<usize> {
+    // min_by_key keeps the first of equal ranks, so enumeration order breaks ties.
+    candidates.iter().enumerate().min_by_key(|(_,candidate)| rank(candidate.info.device_type)).map(|(i,_)| i)
+}
+fn apply_policy(candidates: &[AdapterCandidate],policy: &AdapterPolicy) -> Option<usize> {
+    match policy {
+        AdapterPolicy::HighPerformance => best_by(candidates,high_performance_rank),
+        AdapterPolicy::LowPower => best_by(candidates,low_power_rank),
+        AdapterPolicy::Index(index) => (*index < candidates.len()).then_some(*index),
+        AdapterPolicy::Name(name) => {
+            let name = name.to_lowercase();
+            candidates.iter().position(|candidate| candidate.info.name.to_lowercase().contains(&name))
+        },
+    }
+}
+
+pub fn select_adapter(candidates: &[AdapterCandidate],requested_luid: Option<u64>,policy: &AdapterPolicy) -> Option<(usize,SelectionReason)> {
+    if let Some(luid) = requested_luid {
+        if let Some(index) = candidates.iter().position(|candidate| candidate.luid == Some(luid)) {
+            return Some((index,SelectionReason::MatchedLuid(luid)));
+        }
+        log::warn!("No adapter matches the requested LUID {:#018x}, applying {:?}",luid,policy);
+    }
+    if let Some(index) = apply_policy(candidates,policy) {
+        return Some((index,SelectionReason::Policy(policy.clone())));
+    }
+    best_by(candidates,high_performance_rank).map(|index| (index,SelectionReason::PolicyFallback(policy.clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str,device_type: DeviceType,luid: Option<u64>) -> AdapterCandidate {
+        AdapterCandidate {
+            info: AdapterInfo {
+                name: name.to_string(),
+                vendor: 0,
+                device: 0,
+                device_type,
+                driver: String::new(),
+                driver_info: String::new(),
+                backend: wgpu::Backend::Dx12,
+            },
+            luid,
+        }
+    }
+    fn machine() -> Vec<AdapterCandidate> {
+        vec![
+            candidate("Intel(R) UHD Graphics 770",DeviceType::IntegratedGpu,Some(0x1111)),
+            candidate("NVIDIA GeForce RTX 4070",DeviceType::DiscreteGpu,Some(0x2222)),
+            candidate("Microsoft Basic Render Driver",DeviceType::Cpu,Some(0x3333)),
+        ]
+    }
+
+    #[test]
+    fn an_exact_luid_wins_over_the_policy() {
+        let candidates = machine();
+        assert_eq!(select_adapter(&candidates,Some(0x1111),&AdapterPolicy::HighPerformance),Some((0,SelectionReason::MatchedLuid(0x1111))));
+        // Even a software adapter, if that is what Unity asked for.
+        assert_eq!(select_adapter(&candidates,Some(0x3333),&AdapterPolicy::HighPerformance),Some((2,SelectionReason::MatchedLuid(0x3333))));
+    }
+
+    #[test]
+    fn a_luid_miss_falls_back_to_the_policy() {
+        let candidates = machine();
+        assert_eq!(select_adapter(&candidates,Some(0x9999),&AdapterPolicy::HighPerformance),Some((1,SelectionReason::Policy(AdapterPolicy::HighPerformance))));
+        assert_eq!(select_adapter(&candidates,Some(0x9999),&AdapterPolicy::LowPower),Some((0,SelectionReason::Policy(AdapterPolicy::LowPower))));
+        // Candidates without a LUID (non-D3D12 backends) never match one.
+        let no_luids = machine().into_iter().map(|c| AdapterCandidate { luid: None, ..c }).collect::<Vec<_>>();
+        assert_eq!(select_adapter(&no_luids,Some(0x1111),&AdapterPolicy::HighPerformance).map(|(i,_)| i),Some(1));
+    }
+
+    #[test]
+    fn applies_index_and_name_policies() {
+        let candidates = machine();
+        let name = AdapterPolicy::Name(String::from("geforce"));
+        assert_eq!(select_adapter(&candidates,None,&name),Some((1,SelectionReason::Policy(name.clone()))));
+        assert_eq!(select_adapter(&candidates,None,&AdapterPolicy::Index(2)),Some((2,SelectionReason::Policy(AdapterPolicy::Index(2)))));
+    }
+
+    #[test]
+    fn a_policy_that_matches_nothing_falls_back_to_high_performance() {
+        let candidates = machine();
+        let name = AdapterPolicy::Name(String::from("radeon"));
+        assert_eq!(select_adapter(&candidates,None,&name),Some((1,SelectionReason::PolicyFallback(name.clone()))));
+        assert_eq!(select_adapter(&candidates,None,&AdapterPolicy::Index(3)),Some((1,SelectionReason::PolicyFallback(AdapterPolicy::Index(3)))));
+    }
+
+    #[test]
+    fn handles_empty_and_software_only_lists() {
+        assert_eq!(select_adapter(&[],Some(0x1111),&AdapterPolicy::HighPerformance),None);
+        assert_eq!(select_adapter(&[],None,&AdapterPolicy::Index(0)),None);
+        let software = vec![candidate("Microsoft Basic Render Driver",DeviceType::Cpu,None)];
+        assert_eq!(select_adapter(&software,None,&AdapterPolicy::HighPerformance),Some((0,SelectionReason::Policy(AdapterPolicy::HighPerformance))));
+        assert_eq!(select_adapter(&software,None,&AdapterPolicy::LowPower),Some((0,SelectionReason::Policy(AdapterPolicy::LowPower))));
+    }
+
+    #[test]
+    fn ties_keep_enumeration_order() {
+        let candidates = vec![
+            candidate("first",DeviceType::DiscreteGpu,None),
+            candidate("second",DeviceType::DiscreteGpu,None),
+        ];
+        assert_eq!(select_adapter(&candidates,None,&AdapterPolicy::HighPerformance).map(|(i,_)| i),Some(0));
+    }
+}
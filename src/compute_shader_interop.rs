@@ -1,74 +1,381 @@
-use std::ffi::{c_char, c_void, CString};
-use std::sync::Arc;
-use spirv_cross2::spirv::Capability::Kernel;
-use wgpu::{BindGroupDescriptor, BindGroupEntry, BindingResource, BufferBinding, ComputePassDescriptor, ComputePipelineDescriptor, PipelineLayoutDescriptor, TextureDescriptor};
-use wgpu::custom::AsAny;
-use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use wgpu_types::{BufferAddress, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, TextureFormat, TextureUsages, TextureViewDescriptor};
-use windows::core::Interface;
-use windows::Win32::Graphics::Direct3D12::ID3D12Resource;
-use crate::DEVICE;
-use crate::render::KERNELS;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr};
+use std::sync::{Mutex, Once};
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BindingResource, BufferDescriptor, ComputePassDescriptor, ComputePipelineDescriptor, Device, PipelineLayoutDescriptor, Queue};
+use wgpu_types::{BufferUsages, CommandEncoderDescriptor, Extent3d, TextureFormat, TextureViewDescriptor};
+use crate::{device, DEVICE};
+use crate::crash;
+use crate::crash::InteropCall;
+use crate::memory::{self, Tracked};
+use crate::{hot_reload, shader_cache};
+use crate::render::{build_kernel, current_backend, uses_ray_query, BuiltKernel, KERNELS};
 use crate::shaders::KernelStruct;
+use crate::standalone::{OwnedBuffer, OwnedTexture};
 
-pub struct ComputeShader<'a> {
+pub enum BoundResource {
+    Buffer { buffer: wgpu::Buffer, _memory: Tracked },
+    Texture { texture: wgpu::Texture, view: wgpu::TextureView, _memory: Tracked },
+}
+impl BoundResource {
+    fn as_binding_resource(&self) -> BindingResource<'_> {
+        match self {
+            BoundResource::Buffer { buffer, .. } => buffer.as_entire_binding(),
+            BoundResource::Texture { view, .. } => BindingResource::TextureView(view),
+        }
+    }
+    fn describe(&self) -> String {
+        match self {
+            BoundResource::Buffer { buffer, .. } => format!("buffer, {} bytes",buffer.size()),
+            BoundResource::Texture { texture, .. } => format!("texture {:?} {}x{}x{}, {} mips",texture.format(),texture.width(),texture.height(),texture.depth_or_array_layers(),texture.mip_level_count()),
+        }
+    }
+}
+impl KernelStruct {
+    fn binding_name(&self,kernel: &str,binding: u32) -> Option<&str> {
+        self.kernel_name_and_name_to_binding.iter()
+            .find(|((k,_),b)| k == kernel && **b == binding)
+            .map(|((_,name),_)| name.as_str())
+    }
+}
+
+pub struct ComputeShader {
     pub(crate) inner_shader: KernelStruct,
-    pub(crate) bind_group_entries: Vec<BindGroupEntry<'a>>
+    // Kernel group this was loaded from, e.g. "IntersectionKernels".
+    pub(crate) group: String,
+    // False for groups from ComputeShader_LoadFromSpirv. Those never go back to the registry or pick up hot reloads,
+    // so they can share a name with an embedded group.
+    pub(crate) embedded: bool,
+    pub(crate) bound_resources: HashMap<(u32,u32),BoundResource>,
+    // Uniform buffer holding `inner_shader.globals`, created on the first dispatch and rewritten before each one.
+    pub(crate) globals_buffer: Option<(wgpu::Buffer,Tracked)>,
+    // Device generation the kernel objects were built on.
+    pub(crate) generation: u64,
+}
+
+impl ComputeShader {
+    // Swaps in a rebuilt kernel and moves its bound resources to their new binding slots by name.
+    // Resources whose name the new build no longer declares are dropped.
+    pub(crate) fn reload_kernel(&mut self,kernel: &str,built: BuiltKernel) {
+        let indices = self.inner_shader.kernel_index_to_name.iter().filter(|(_,name)| *name == kernel).map(|(index,_)| *index).collect::<Vec<_>>();
+        let mut moved = Vec::new();
+        for index in &indices {
+            let keys = self.bound_resources.keys().filter(|(i,_)| i == index).copied().collect::<Vec<_>>();
+            for key in keys {
+                let name = self.inner_shader.binding_name(kernel,key.1).map(String::from);
+                let resource = self.bound_resources.remove(&key).unwrap();
+                if let Some(name) = name {
+                    moved.push((*index,name,resource));
+                }
+            }
+        }
+        self.inner_shader.replace_kernel(kernel,built);
+        for (index,name,resource) in moved {
+            match self.inner_shader.kernel_name_and_name_to_binding.get(&(kernel.to_string(),name.clone())) {
+                Some(binding) => {
+                    self.bound_resources.insert((index,*binding),resource);
+                },
+                None => log::warn!("{} no longer declares {}, unbinding it",kernel,name),
+            }
+        }
+    }
+}
+
+// Every handle given to C# and not yet released, so shutdown can drop their GPU objects before the device.
+static LIVE_SHADERS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+fn hand_out(shader: Box<ComputeShader>) -> *mut ComputeShader {
+    let raw = Box::into_raw(shader);
+    LIVE_SHADERS.lock().unwrap().push(raw as usize);
+    raw
+}
+
+// The handles themselves stay allocated, since C# may still hold them; every call on one is skipped from here on
+// and ComputeShader_Release frees it.
+pub(crate) fn release_live_shaders() {
+    let live = std::mem::take(&mut *LIVE_SHADERS.lock().unwrap());
+    for raw in &live {
+        let shader = unsafe {&mut *(*raw as *mut ComputeShader)};
+        shader.bound_resources.clear();
+        shader.globals_buffer = None;
+        shader.inner_shader = KernelStruct::default();
+    }
+    if !live.is_empty() {
+        log::debug!("Released the GPU objects of {} live ComputeShader handles",live.len());
+    }
+}
+
+// A lost device, or a handle loaded before the last recovery, can't be used; skip the call instead of touching dead objects.
+fn device_usable(shader: &ComputeShader,call: &str) -> bool {
+    if !device::is_ready() {
+        log::debug!("{} skipped, device status {}",call,device::status());
+        return false;
+    }
+    if shader.generation != device::generation() {
+        log::warn!("{} on a ComputeShader loaded before device recovery, reload it",call);
+        return false;
+    }
+    true
+}
+
+// Approximate footprint: every mip level at the format's block size, ignoring driver padding.
+pub(crate) fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    let block = texture.format().block_copy_size(None).unwrap_or(4) as u64;
+    (0..texture.mip_level_count()).map(|mip| {
+        let size = texture.size().mip_level_size(mip,texture.dimension());
+        size.width as u64 * size.height as u64 * size.depth_or_array_layers as u64 * block
+    }).sum()
+}
+
+// Native resources can only be wrapped by a D3D12 device; these return None on any other backend, e.g. a Vulkan
+// standalone device, and the calling export skips the call.
+fn import_buffer(device: &Device,buf: *mut c_void,buf_size: usize,usage: BufferUsages) -> Option<wgpu::Buffer> {
+    #[cfg(windows)]
+    return unsafe {crate::shim::import_buffer(device,buf,buf_size,usage)};
+    #[cfg(not(windows))]
+    None
+}
+fn import_texture(device: &Device,tex: *mut c_void,size: Extent3d,format: TextureFormat,dimension: wgpu_types::TextureDimension,mip_count: u32) -> Option<wgpu::Texture> {
+    #[cfg(windows)]
+    return unsafe {crate::shim::import_texture(device,tex,size,format,dimension,mip_count)};
+    #[cfg(not(windows))]
+    None
+}
+// Logged as an error the first time only, since Unity repeats these calls every frame.
+fn import_unsupported(call: &str) {
+    static REPORTED: Once = Once::new();
+    let mut first = false;
+    REPORTED.call_once(|| first = true);
+    if first {
+        log::error!("{} skipped: native resources can only be bound on the D3D12 backend, this device is {:?}",call,current_backend());
+    } else {
+        log::debug!("{} skipped, native resources need the D3D12 backend",call);
+    }
+}
+
+// Names passed by C# stay owned by C#, so they are only borrowed for the call.
+fn borrow_name(name: *const c_char,call: &str) -> Option<String> {
+    if name.is_null() {
+        log::error!("{} called without a name",call);
+        return None;
+    }
+    match unsafe {CStr::from_ptr(name)}.to_str() {
+        Ok(name) => Some(name.to_string()),
+        Err(_) => {
+            log::error!("{} called with a name that isn't UTF-8",call);
+            None
+        }
+    }
+}
+
+impl ComputeShader {
+    fn kernel_name(&self,kernel_index: u32,call: &str) -> Option<String> {
+        let name = self.inner_shader.kernel_index_to_name.get(&kernel_index).cloned();
+        if name.is_none() {
+            log::error!("{} with unknown kernel index {} on {}",call,kernel_index,self.group);
+        }
+        name
+    }
+    // The kernel's name and the binding slot it declares `name` at.
+    fn binding(&self,kernel_index: u32,name: &str,call: &str) -> Option<(String,u32)> {
+        let kernel_name = self.kernel_name(kernel_index,call)?;
+        let Some(binding) = self.inner_shader.kernel_name_and_name_to_binding.get(&(kernel_name.clone(),name.to_string())).copied() else {
+            log::error!("{}: {} has no binding named {}",call,kernel_name,name);
+            return None;
+        };
+        Some((kernel_name,binding))
+    }
+    // Copies `bytes` into the $Globals data at `name`'s offset.
+    fn set_global(&mut self,name: &str,call: &str,bytes: &[u8]) {
+        let Some(offset) = self.inner_shader.name_to_globals_offset.get(name).map(|offset| *offset as usize) else {
+            log::error!("{} on {}: no global named {}",call,self.group,name);
+            return;
+        };
+        match self.inner_shader.globals.get_mut(offset..offset + bytes.len()) {
+            Some(slot) => slot.copy_from_slice(bytes),
+            None => log::error!("{} on {}: {} at offset {} runs past $Globals",call,self.group,name,offset),
+        }
+    }
+    // Uploads $Globals into the shader's buffer, (re)creating it only when missing or resized.
+    fn write_globals(&mut self,device: &Device,queue: &Queue) {
+        let globals = &self.inner_shader.globals;
+        if globals.is_empty() {
+            return;
+        }
+        if self.globals_buffer.as_ref().is_none_or(|(buffer,_)| buffer.size() != globals.len() as u64) {
+            let buffer = device.create_buffer(&BufferDescriptor {
+                label: Some("$Globals"),
+                size: globals.len() as u64,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let memory = memory::track_created(buffer.size(),memory::MEMORY_GLOBALS);
+            self.globals_buffer = Some((buffer,memory));
+        }
+        queue.write_buffer(&self.globals_buffer.as_ref().unwrap().0,0,globals);
+    }
 }
 
+// Returned by ComputeShader_FindKernel for a name the shader doesn't declare.
+pub const KERNEL_NOT_FOUND: u32 = u32::MAX;
+
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_Load(shader: *mut c_char) -> *mut ComputeShader<'static> {
-    let shader_binding = unsafe {CString::from_raw(shader)};
-    let shader_str = shader_binding.to_str().unwrap();
+pub extern "C" fn ComputeShader_Load(shader: *const c_char) -> *mut ComputeShader {
+    // Owned by C#, only borrowed for the call.
+    let shader_binding = unsafe {CStr::from_ptr(shader)}.to_string_lossy();
+    let shader_str = shader_binding.as_ref();
+    // Unity's asset path, e.g. "MainCompute/ReSTIRGI"; the group is its last segment.
+    let group = shader_str.rsplit('/').next().unwrap().to_string();
     let mut binding = KERNELS.get_mut();
-    let kernel_structs = binding.as_mut().unwrap();
-    let strct = Box::new(ComputeShader {
-        inner_shader: match shader_str {
-            "MainCompute/IntersectionKernels" => kernel_structs.intersection_kernels.take().unwrap(),
-            "MainCompute/RayGenKernels" => kernel_structs.raygen_kernels.take().unwrap(),
-            "MainCompute/RayTracingShader" => kernel_structs.ray_tracing_shader.take().unwrap(),
-            "MainCompute/ReSTIRGI" => kernel_structs.restir_gi.take().unwrap(),
-            "Utility/GeneralMeshFunctions" => kernel_structs.general_mesh_functions.take().unwrap(),
-            "Utility/CopyTextureShader" => kernel_structs.copy_texture_shader.take().unwrap(),
-            "Utility/BVHRefitter" => kernel_structs.bvh_refitter.take().unwrap(),
-            _ => unimplemented!()
-        },
-        bind_group_entries: Vec::new(),
+    let Some(inner_shader) = binding.as_mut().unwrap().groups.remove(&group) else {
+        log::error!("No kernel group {} to load for {} (unknown, or already loaded)",group,shader_str);
+        return std::ptr::null_mut();
+    };
+    let mut strct = Box::new(ComputeShader {
+        inner_shader,
+        group,
+        embedded: true,
+        bound_resources: HashMap::new(),
+        globals_buffer: None,
+        generation: device::generation(),
     });
-  //  KERNELS.get_mut().replace(binding);
-    Box::into_raw(strct)
+    drop(binding);
+    hot_reload::apply_pending(&mut strct);
+    hand_out(strct)
+}
+
+// A shader group that isn't part of TrueTrace, e.g. a project's own post-process kernels. `kernels` and `spirv`/`spirv_sizes`
+// hold `kernel_count` entries; a kernel's index is its position in the arrays. The strings and blobs are only read during the call.
+// `name` is only a label: it may match an embedded group without touching the registry.
+// Returns null if a pointer is null, the device isn't ready or any kernel fails to reflect or translate.
+#[unsafe(no_mangle)]
+pub extern "C" fn ComputeShader_LoadFromSpirv(name: *const c_char,kernel_count: u32,kernels: *const *const c_char,spirv: *const *const u8,spirv_sizes: *const usize) -> *mut ComputeShader {
+    if name.is_null() || kernels.is_null() || spirv.is_null() || spirv_sizes.is_null() {
+        log::error!("ComputeShader_LoadFromSpirv called with a null name or kernel array");
+        return std::ptr::null_mut();
+    }
+    let name = unsafe {CStr::from_ptr(name)}.to_string_lossy().into_owned();
+    if !device::is_ready() {
+        log::error!("Can't load {} while the device status is {}",name,device::status());
+        return std::ptr::null_mut();
+    }
+    let kernels = unsafe {std::slice::from_raw_parts(kernels,kernel_count as usize)};
+    let spirv = unsafe {std::slice::from_raw_parts(spirv,kernel_count as usize)};
+    let spirv_sizes = unsafe {std::slice::from_raw_parts(spirv_sizes,kernel_count as usize)};
+    let backend = current_backend();
+    let has_ray_query = device::has_capability(device::CAPABILITY_RAY_QUERY);
+    let generation = device::generation();
+    let device_binding = DEVICE.get_mut();
+    let (wgpu_device,_) = device_binding.as_ref().unwrap();
+    let mut inner_shader = KernelStruct::default();
+    for index in 0..kernel_count as usize {
+        if kernels[index].is_null() || spirv[index].is_null() {
+            log::error!("Kernel {} of {} has a null name or SPIR-V blob",index,name);
+            return std::ptr::null_mut();
+        }
+        let kernel = unsafe {CStr::from_ptr(kernels[index])}.to_string_lossy().into_owned();
+        let bytes = unsafe {std::slice::from_raw_parts(spirv[index],spirv_sizes[index])};
+        if !has_ray_query && uses_ray_query(bytes) {
+            log::error!("{}/{} needs ray query, which this device lacks",name,kernel);
+            return std::ptr::null_mut();
+        }
+        match build_kernel(wgpu_device,backend,&kernel,bytes) {
+            Ok(built) => inner_shader.insert_kernel(&kernel,built),
+            Err(err) => {
+                log::error!("Could not build {}/{}: {}",name,kernel,err);
+                return std::ptr::null_mut();
+            }
+        }
+        inner_shader.kernel_index_to_name.insert(index as u32,kernel);
+    }
+    log::info!("Loaded {} with {} kernels",name,kernel_count);
+    hand_out(Box::new(ComputeShader {
+        inner_shader,
+        group: name,
+        embedded: false,
+        bound_resources: HashMap::new(),
+        globals_buffer: None,
+        generation,
+    }))
+}
+
+// Frees the handle and everything bound to it. An embedded group goes back to the registry, so it can be loaded again.
+#[unsafe(no_mangle)]
+pub extern "C" fn ComputeShader_Release(_self: *mut ComputeShader) {
+    if _self.is_null() {
+        return;
+    }
+    LIVE_SHADERS.lock().unwrap().retain(|raw| *raw != _self as usize);
+    let shader = unsafe {Box::from_raw(_self)};
+    let ComputeShader { inner_shader, group, embedded, generation, .. } = *shader;
+    if !embedded || generation != device::generation() || inner_shader.kernel_index_to_name.is_empty() {
+        return;
+    }
+    if let Some(kernels) = KERNELS.get_mut().as_mut() {
+        kernels.groups.entry(group).or_insert(inner_shader);
+    }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn ComputeShader_Dispatch(_self: *mut ComputeShader,kernel_index: u32,x: u32,y: u32,z: u32) {
     let mut self_to_use = unsafe {Box::from_raw(_self)};
+    if !device_usable(&self_to_use,"Dispatch") {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    }
+    hot_reload::apply_pending(&mut self_to_use);
+    let Some(kernel_name) = self_to_use.kernel_name(kernel_index,"Dispatch") else {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
+    let backend = current_backend();
     let mut device_binding = DEVICE.get_mut();
     let (device,queue) = device_binding.as_mut().unwrap();
-    let kernel_name = self_to_use.inner_shader.kernel_index_to_name.get(&kernel_index).unwrap().clone();
-    let (bgl,module) = self_to_use.inner_shader.kernel_to_bgl_and_shader_mod.get(&kernel_name).unwrap();
-    let globals_buffer = device.create_buffer_init(&BufferInitDescriptor {
-        label: Some("$Globals"),
-        contents: &self_to_use.inner_shader.globals,
-        usage:  BufferUsages::UNIFORM,
-    });
+    self_to_use.inner_shader.ensure_compiled(device,backend,&kernel_name);
+    let mut call = InteropCall::new("Dispatch",kernel_name.as_str());
+    call.dispatch = Some([x,y,z]);
+    call.resources = self_to_use.bound_resources.iter()
+        .filter(|((index,_),_)| *index == kernel_index)
+        .map(|((_,binding),resource)| format!("{} (binding {}): {}",self_to_use.inner_shader.binding_name(&kernel_name,*binding).unwrap_or("?"),binding,resource.describe()))
+        .collect();
+    crash::record(call);
+    self_to_use.write_globals(device,queue);
+    let Some((bgl,module)) = self_to_use.inner_shader.kernel_to_bgl_and_shader_mod.get(&kernel_name) else {
+        log::error!("Kernel {} was not built for this device, skipping dispatch",kernel_name);
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
+    // Bound here unless something was explicitly set at the same slot.
+    let globals_binding = self_to_use.inner_shader.kernel_name_and_name_to_binding.get(&(kernel_name.clone(),String::from("$Globals"))).copied()
+        .filter(|binding| !self_to_use.bound_resources.contains_key(&(kernel_index,*binding)));
     let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
     let compute_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
         label: None,
         bind_group_layouts: &[bgl],
         push_constant_ranges: &[],
     });
-    let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+    let compute_pipeline = shader_cache::with_pipeline_cache(|cache| device.create_compute_pipeline(&ComputePipelineDescriptor {
         label: None,
         layout: Some(&compute_pipeline_layout),
         module,
         entry_point: None,
         compilation_options: Default::default(),
-        cache: None,
-    });
+        cache,
+    }));
+    let entries = self_to_use.bound_resources.iter()
+        .filter(|((index,_),_)| *index == kernel_index)
+        .map(|((_,binding),resource)| BindGroupEntry {
+            binding: *binding,
+            resource: resource.as_binding_resource(),
+        })
+        .chain(globals_binding.zip(self_to_use.globals_buffer.as_ref()).map(|(binding,(buffer,_))| BindGroupEntry {
+            binding,
+            resource: buffer.as_entire_binding(),
+        }))
+        .collect::<Vec<_>>();
     let bind_group = device.create_bind_group(&BindGroupDescriptor {
         label: None,
         layout: bgl,
-        entries: &self_to_use.bind_group_entries
+        entries: &entries
     });
     {
         let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
@@ -83,137 +390,241 @@ pub extern "C" fn ComputeShader_Dispatch(_self: *mut ComputeShader,kernel_index:
     unsafe {Box::into_raw(self_to_use)};
 }
 
+// Compiles kernels ahead of their first dispatch in lazy mode, e.g. behind a loading screen. Already compiled
+// kernels are skipped. Returns false if any index is unknown or fails to compile.
+#[unsafe(no_mangle)]
+pub extern "C" fn ComputeShader_Prewarm(_self: *mut ComputeShader,kernel_indices: *const u32,kernel_count: u32) -> bool {
+    let mut self_to_use = unsafe {Box::from_raw(_self)};
+    if !device_usable(&self_to_use,"Prewarm") {
+        unsafe {Box::into_raw(self_to_use)};
+        return false;
+    }
+    let indices = unsafe {std::slice::from_raw_parts(kernel_indices,kernel_count as usize)};
+    let kernels = indices.iter().filter_map(|index| self_to_use.inner_shader.kernel_index_to_name.get(index).cloned()).collect::<Vec<_>>();
+    let backend = current_backend();
+    let device_binding = DEVICE.get_mut();
+    let (device,_) = device_binding.as_ref().unwrap();
+    let all_ok = self_to_use.inner_shader.prewarm(device,backend,&kernels) && kernels.len() == indices.len();
+    drop(device_binding);
+    unsafe {Box::into_raw(self_to_use)};
+    all_ok
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn ComputeShader_DispatchIndirect(_self: *mut ComputeShader,kernel_index: u32,buf: *mut c_void,buf_size: usize) {
+    if !device::is_ready() {
+        return;
+    }
     let mut device_binding = DEVICE.get_mut();
     let (device,queue) = device_binding.as_mut().unwrap();
-    let indirect_buf_wgpu_hal = unsafe {wgpu_hal::dx12::Device::buffer_from_raw(ID3D12Resource::from_raw(buf), buf_size as BufferAddress)};
-    let indirect_buf_wgpu = unsafe {device.create_buffer_from_hal::<wgpu_hal::dx12::Api>(indirect_buf_wgpu_hal,&BufferDescriptor {
-        label: None,
-        size: buf_size as BufferAddress,
-        usage: BufferUsages::INDIRECT,
-        mapped_at_creation: false,
-    })};
+    let Some(indirect_buf_wgpu) = import_buffer(device,buf,buf_size,BufferUsages::INDIRECT) else {
+        import_unsupported("DispatchIndirect");
+        return;
+    };
+    let _indirect_memory = memory::track_native(buf,buf_size as u64,memory::MEMORY_OTHER);
+    let mut call = InteropCall::new("DispatchIndirect",format!("kernel {}",kernel_index));
+    call.resources.push(format!("indirect args: buffer, {} bytes",buf_size));
+    crash::record(call);
 
 }
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_FindKernel(_self: *mut ComputeShader,name: *mut c_char) -> u32 {
-    let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
-    let index = *self_to_use.inner_shader.kernel_index_to_name.keys().find(|ind| {
-        self_to_use.inner_shader.kernel_index_to_name.get(*ind).unwrap().clone() == name_str
-    }).unwrap();
+pub extern "C" fn ComputeShader_FindKernel(_self: *mut ComputeShader,name: *const c_char) -> u32 {
+    let Some(name_str) = borrow_name(name,"FindKernel") else {
+        return KERNEL_NOT_FOUND;
+    };
+    let self_to_use = unsafe {Box::from_raw(_self)};
+    let index = self_to_use.inner_shader.kernel_index_to_name.iter().find(|(_,kernel)| **kernel == name_str).map(|(index,_)| *index);
+    if index.is_none() {
+        log::error!("{} has no kernel named {}",self_to_use.group,name_str);
+    }
     unsafe {Box::into_raw(self_to_use)};
-    index
+    index.unwrap_or(KERNEL_NOT_FOUND)
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_SetBool(_self: *mut ComputeShader,name: *mut c_char,val: bool) {
+pub extern "C" fn ComputeShader_SetBool(_self: *mut ComputeShader,name: *const c_char,val: bool) {
+    let Some(name_str) = borrow_name(name,"SetBool") else {
+        return;
+    };
     let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
-    let offset = *self_to_use.inner_shader.name_to_globals_offset.get(&name_str).unwrap() as usize;
     let val_to_set = val as u32;
-    self_to_use.inner_shader.globals[offset..offset+4].copy_from_slice(&val_to_set.to_le_bytes());
+    self_to_use.set_global(&name_str,"SetBool",&val_to_set.to_le_bytes());
     unsafe {Box::into_raw(self_to_use)};
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_SetBuffer(_self: *mut ComputeShader,kernel_index: u32,name: *mut c_char,buf: *mut c_void,buf_size: usize) {
+pub extern "C" fn ComputeShader_SetBuffer(_self: *mut ComputeShader,kernel_index: u32,name: *const c_char,buf: *mut c_void,buf_size: usize) {
+    let Some(name_str) = borrow_name(name,"SetBuffer") else {
+        return;
+    };
     let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
+    if !device_usable(&self_to_use,"SetBuffer") {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    }
+    let Some((kernel_name,binding)) = self_to_use.binding(kernel_index,&name_str,"SetBuffer") else {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
     let mut device_binding = DEVICE.get_mut();
     let (device,queue) = device_binding.as_mut().unwrap();
-    let buf_wgpu_hal = unsafe {wgpu_hal::dx12::Device::buffer_from_raw(ID3D12Resource::from_raw(buf), buf_size as BufferAddress)};
-    let buf_wgpu = unsafe {device.create_buffer_from_hal::<wgpu_hal::dx12::Api>(buf_wgpu_hal,&BufferDescriptor {
-        label: None,
-        size: buf_size as BufferAddress,
-        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    })};
-    let binding = *self_to_use.inner_shader.kernel_name_and_name_to_binding.get(&(self_to_use.inner_shader.kernel_index_to_name.get(&kernel_index).unwrap().clone(),name_str)).unwrap();
-    self_to_use.bind_group_entries.push(BindGroupEntry {
-        binding,
-        resource: buf_wgpu.as_entire_binding(),
-    });
+    let Some(buf_wgpu) = import_buffer(device,buf,buf_size,BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST) else {
+        drop(device_binding);
+        import_unsupported("SetBuffer");
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
+    let mut call = InteropCall::new("SetBuffer",kernel_name.as_str());
+    call.resources.push(format!("{}: buffer, {} bytes",name_str,buf_size));
+    crash::record(call);
+    let memory = memory::track_native(buf,buf_size as u64,memory::categorize(&name_str,false));
+    self_to_use.bound_resources.insert((kernel_index,binding),BoundResource::Buffer { buffer: buf_wgpu, _memory: memory });
     unsafe {Box::into_raw(self_to_use)};
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_SetFloat(_self: *mut ComputeShader,name: *mut c_char,val: f32) {
+pub extern "C" fn ComputeShader_SetFloat(_self: *mut ComputeShader,name: *const c_char,val: f32) {
+    let Some(name_str) = borrow_name(name,"SetFloat") else {
+        return;
+    };
     let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
-    let offset = *self_to_use.inner_shader.name_to_globals_offset.get(&name_str).unwrap() as usize;
-    self_to_use.inner_shader.globals[offset..offset+4].copy_from_slice(&val.to_le_bytes());
+    self_to_use.set_global(&name_str,"SetFloat",&val.to_le_bytes());
     unsafe {Box::into_raw(self_to_use)};
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_SetInt(_self: *mut ComputeShader,name: *mut c_char,val: i32) {
+pub extern "C" fn ComputeShader_SetInt(_self: *mut ComputeShader,name: *const c_char,val: i32) {
+    let Some(name_str) = borrow_name(name,"SetInt") else {
+        return;
+    };
     let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
-    let offset = *self_to_use.inner_shader.name_to_globals_offset.get(&name_str).unwrap() as usize;
-    self_to_use.inner_shader.globals[offset..offset+4].copy_from_slice(&val.to_le_bytes());
+    self_to_use.set_global(&name_str,"SetInt",&val.to_le_bytes());
     unsafe {Box::into_raw(self_to_use)};
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_SetMatrix(_self: *mut ComputeShader,name: *mut c_char,mat: *const u8) {
+pub extern "C" fn ComputeShader_SetMatrix(_self: *mut ComputeShader,name: *const c_char,mat: *const u8) {
+    let Some(name_str) = borrow_name(name,"SetMatrix") else {
+        return;
+    };
     let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
     let mat_slice = unsafe {std::slice::from_raw_parts(mat,16 * 4)};
-    let offset = *self_to_use.inner_shader.name_to_globals_offset.get(&name_str).unwrap() as usize;
-    self_to_use.inner_shader.globals[offset..offset+(16 * 4)].copy_from_slice(mat_slice);
+    self_to_use.set_global(&name_str,"SetMatrix",mat_slice);
     unsafe {Box::into_raw(self_to_use)};
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_SetTexture(_self: *mut ComputeShader,kernel_index: u32,name: *mut c_char,tex: *mut c_void, width: u32, height: u32,format: u32,dimension: i32,mipCnt: u32) {
+pub extern "C" fn ComputeShader_SetTexture(_self: *mut ComputeShader,kernel_index: u32,name: *const c_char,tex: *mut c_void, width: u32, height: u32,format: u32,dimension: i32,mipCnt: u32) {
+    let Some(name_str) = borrow_name(name,"SetTexture") else {
+        return;
+    };
     let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
-    let kernel_name = self_to_use.inner_shader.kernel_index_to_name.get(&kernel_index).unwrap().clone();
-    let binding = *self_to_use.inner_shader.kernel_name_and_name_to_binding.get(&(kernel_name,name_str)).unwrap();
-    let mut device_binding = DEVICE.get_mut();
-    let (device,queue) = device_binding.as_mut().unwrap();
+    if !device_usable(&self_to_use,"SetTexture") {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    }
+    let Some((kernel_name,binding)) = self_to_use.binding(kernel_index,&name_str,"SetTexture") else {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
+    let mut call = InteropCall::new("SetTexture",kernel_name.as_str());
+    call.resources.push(format!("{}: texture format {} dimension {} {}x{}, {} mips",name_str,format,dimension,width,height,mipCnt));
+    crash::record(call);
+    let texture_category = memory::categorize(&name_str,true);
     let unity_tex_fmt: UnityTextureEnum = unsafe {std::mem::transmute(format)};
     let unity_dim: TextureDimension = unsafe {std::mem::transmute(dimension)};
-    let tex_wgpu_hal = unsafe {wgpu_hal::dx12::Device::texture_from_raw(ID3D12Resource::from_raw(tex), TextureFormat::try_from(unity_tex_fmt).unwrap(),wgpu_types::TextureDimension::try_from(unity_dim).unwrap(),Extent3d {
+    let (format,dimension) = match (TextureFormat::try_from(unity_tex_fmt),wgpu_types::TextureDimension::try_from(unity_dim)) {
+        (Ok(format),Ok(dimension)) => (format,dimension),
+        (Err(err),_) | (_,Err(err)) => {
+            log::error!("Can't bind {} ({:?}, {:?}): {}",name_str,unity_tex_fmt,unity_dim,err);
+            unsafe {Box::into_raw(self_to_use)};
+            return;
+        }
+    };
+    let mut device_binding = DEVICE.get_mut();
+    let (device,queue) = device_binding.as_mut().unwrap();
+    let Some(tex_wgpu) = import_texture(device,tex,Extent3d {
         width,
         height,
         depth_or_array_layers: 1
-    },mipCnt,1)};
-
-    let tex_wgpu = unsafe {device.create_texture_from_hal::<wgpu_hal::dx12::Api>(tex_wgpu_hal,&TextureDescriptor {
-        mip_level_count: mipCnt,
-        label: None,
-        size: Extent3d {
-            width,
-            height,
-            depth_or_array_layers: 1
-        },
-        sample_count: 1,
-        dimension: wgpu_types::TextureDimension::try_from(unity_dim).unwrap(),
-        format: TextureFormat::try_from(unity_tex_fmt).unwrap(),
-        usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
-        view_formats: &[],
-    })};
+    },format,dimension,mipCnt) else {
+        drop(device_binding);
+        import_unsupported("SetTexture");
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
     let view = tex_wgpu.create_view(&TextureViewDescriptor::default());
-    self_to_use.bind_group_entries.push(BindGroupEntry {
-        binding,
-        resource: BindingResource::TextureView(
-            &view,
-        )
-    });
+    let memory = memory::track_native(tex,texture_bytes(&tex_wgpu),texture_category);
+    self_to_use.bound_resources.insert((kernel_index,binding),BoundResource::Texture { texture: tex_wgpu, view, _memory: memory });
     unsafe {Box::into_raw(self_to_use)};
 }
 
 
 #[unsafe(no_mangle)]
-pub extern "C" fn ComputeShader_SetVector(_self: *mut ComputeShader,name: *mut c_char,val: *const u8) {
+pub extern "C" fn ComputeShader_SetVector(_self: *mut ComputeShader,name: *const c_char,val: *const u8) {
+    let Some(name_str) = borrow_name(name,"SetVector") else {
+        return;
+    };
     let mut self_to_use = unsafe {Box::from_raw(_self)};
-    let name_str = unsafe {CString::from_raw(name)}.into_string().unwrap();
     let vec_slice = unsafe {std::slice::from_raw_parts(val,16)};
-    let offset = *self_to_use.inner_shader.name_to_globals_offset.get(&name_str).unwrap() as usize;
-    self_to_use.inner_shader.globals[offset..offset+16].copy_from_slice(vec_slice);
+    self_to_use.set_global(&name_str,"SetVector",vec_slice);
+    unsafe {Box::into_raw(self_to_use)};
+}
+// Binds a buffer from TrueTrace_CreateBuffer; works on every backend, unlike SetBuffer.
+#[unsafe(no_mangle)]
+pub extern "C" fn ComputeShader_SetOwnedBuffer(_self: *mut ComputeShader,kernel_index: u32,name: *const c_char,buffer: *const OwnedBuffer) {
+    let Some(name_str) = borrow_name(name,"SetOwnedBuffer") else {
+        return;
+    };
+    let mut self_to_use = unsafe {Box::from_raw(_self)};
+    let owned = unsafe {&*buffer};
+    if !device_usable(&self_to_use,"SetOwnedBuffer") {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    }
+    if owned.generation != self_to_use.generation {
+        log::warn!("SetOwnedBuffer with a buffer created before device recovery, recreate it");
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    }
+    let Some((kernel_name,binding)) = self_to_use.binding(kernel_index,&name_str,"SetOwnedBuffer") else {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
+    let mut call = InteropCall::new("SetOwnedBuffer",kernel_name.as_str());
+    call.resources.push(format!("{}: buffer, {} bytes",name_str,owned.buffer.size()));
+    crash::record(call);
+    self_to_use.bound_resources.insert((kernel_index,binding),BoundResource::Buffer { buffer: owned.buffer.clone(), _memory: owned.memory.clone() });
+    unsafe {Box::into_raw(self_to_use)};
+}
+
+// Binds a texture from TrueTrace_CreateTexture; works on every backend, unlike SetTexture.
+#[unsafe(no_mangle)]
+pub extern "C" fn ComputeShader_SetOwnedTexture(_self: *mut ComputeShader,kernel_index: u32,name: *const c_char,texture: *const OwnedTexture) {
+    let Some(name_str) = borrow_name(name,"SetOwnedTexture") else {
+        return;
+    };
+    let mut self_to_use = unsafe {Box::from_raw(_self)};
+    let owned = unsafe {&*texture};
+    if !device_usable(&self_to_use,"SetOwnedTexture") {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    }
+    if owned.generation != self_to_use.generation {
+        log::warn!("SetOwnedTexture with a texture created before device recovery, recreate it");
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    }
+    let Some((kernel_name,binding)) = self_to_use.binding(kernel_index,&name_str,"SetOwnedTexture") else {
+        unsafe {Box::into_raw(self_to_use)};
+        return;
+    };
+    let texture = owned.texture.clone();
+    let mut call = InteropCall::new("SetOwnedTexture",kernel_name.as_str());
+    call.resources.push(format!("{}: texture {:?} {}x{}x{}, {} mips",name_str,texture.format(),texture.width(),texture.height(),texture.depth_or_array_layers(),texture.mip_level_count()));
+    crash::record(call);
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    self_to_use.bound_resources.insert((kernel_index,binding),BoundResource::Texture { texture, view, _memory: owned.memory.clone() });
     unsafe {Box::into_raw(self_to_use)};
 }
 #[repr(i32)]
@@ -460,4 +871,79 @@ impl TryFrom<UnityTextureEnum> for TextureFormat {
             _ => Err("Unsupported or unmapped UnityTextureEnum variant"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shader() -> ComputeShader {
+        let mut inner_shader = KernelStruct::default();
+        inner_shader.kernel_index_to_name.insert(0,String::from("Trace"));
+        inner_shader.kernel_name_and_name_to_binding.insert((String::from("Trace"),String::from("Rays")),3);
+        inner_shader.name_to_globals_offset.insert(String::from("Frame"),4);
+        inner_shader.name_to_globals_offset.insert(String::from("Last"),12);
+        inner_shader.globals = vec![0; 16];
+        ComputeShader { inner_shader, group: String::from("Test"), embedded: true, bound_resources: HashMap::new(), globals_buffer: None, generation: 0 }
+    }
+
+    #[test]
+    fn maps_unity_formats() {
+        assert_eq!(TextureFormat::try_from(UnityTextureEnum::R8G8B8A8_UNorm),Ok(TextureFormat::Rgba8Unorm));
+        assert_eq!(TextureFormat::try_from(UnityTextureEnum::R8G8B8A8_SRGB),Ok(TextureFormat::Rgba8UnormSrgb));
+        assert_eq!(TextureFormat::try_from(UnityTextureEnum::R16G16B16A16_SFloat),Ok(TextureFormat::Rgba16Float));
+        assert_eq!(TextureFormat::try_from(UnityTextureEnum::R32G32B32A32_SFloat),Ok(TextureFormat::Rgba32Float));
+        assert_eq!(TextureFormat::try_from(UnityTextureEnum::B8G8R8A8_SRGB),Ok(TextureFormat::Bgra8UnormSrgb));
+        assert!(TextureFormat::try_from(UnityTextureEnum::None).is_err());
+        assert!(TextureFormat::try_from(UnityTextureEnum::R8_SRGB).is_err());
+    }
+
+    #[test]
+    fn unity_enums_keep_their_values() {
+        // C# passes these as plain integers that are transmuted back.
+        assert_eq!(UnityTextureEnum::R8G8B8A8_UNorm as u32,8);
+        assert_eq!(UnityTextureEnum::R16G16B16A16_SFloat as u32,48);
+        assert_eq!(UnityTextureEnum::R32G32B32A32_SFloat as u32,52);
+        assert_eq!(TextureDimension::Unknown as i32,-1);
+        assert_eq!(TextureDimension::Tex2D as i32,2);
+        assert_eq!(TextureDimension::Tex3D as i32,3);
+    }
+
+    #[test]
+    fn maps_unity_dimensions() {
+        assert_eq!(wgpu_types::TextureDimension::try_from(TextureDimension::Tex2D),Ok(wgpu_types::TextureDimension::D2));
+        assert_eq!(wgpu_types::TextureDimension::try_from(TextureDimension::Tex3D),Ok(wgpu_types::TextureDimension::D3));
+        for dimension in [TextureDimension::Unknown, TextureDimension::None, TextureDimension::Any, TextureDimension::Cube, TextureDimension::Tex2DArray, TextureDimension::CubeArray] {
+            assert!(wgpu_types::TextureDimension::try_from(dimension).is_err(),"{:?}",dimension);
+        }
+    }
+
+    #[test]
+    fn borrows_names() {
+        assert_eq!(borrow_name(c"_CameraPos".as_ptr(),"test"),Some(String::from("_CameraPos")));
+        assert_eq!(borrow_name(std::ptr::null(),"test"),None);
+        assert_eq!(borrow_name(c"\xff\xfe".as_ptr(),"test"),None);
+    }
+
+    #[test]
+    fn writes_globals_at_their_offset() {
+        let mut shader = shader();
+        shader.set_global("Frame","test",&7u32.to_le_bytes());
+        assert_eq!(shader.inner_shader.globals[4..8],7u32.to_le_bytes());
+        // Unknown names and writes past the end leave the data alone.
+        shader.set_global("Missing","test",&[1; 4]);
+        shader.set_global("Last","test",&[1; 16]);
+        assert_eq!(shader.inner_shader.globals.iter().filter(|b| **b != 0).count(),1);
+    }
+
+    #[test]
+    fn looks_up_kernels_and_bindings() {
+        let shader = shader();
+        assert_eq!(shader.kernel_name(0,"test"),Some(String::from("Trace")));
+        assert_eq!(shader.kernel_name(1,"test"),None);
+        assert_eq!(shader.binding(0,"Rays","test"),Some((String::from("Trace"),3)));
+        assert_eq!(shader.binding(0,"Hits","test"),None);
+        assert_eq!(shader.binding(1,"Rays","test"),None);
+        assert_eq!(shader.inner_shader.binding_name("Trace",3),Some("Rays"));
+    }
+}
@@ -0,0 +1,529 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use lazy_mut::LazyMut;
+use serde::Deserialize;
+use wgpu::DxcShaderModel;
+
+pub const CONFIG_FILE_NAME: &str = "truetrace.toml";
+
+pub static CONFIG: LazyMut<Config> = LazyMut::new(Config::load_or_default);
+// Config loads before the logger exists, so a load failure is parked here until logging::init reports it.
+static LOAD_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn take_load_error() -> Option<String> {
+    LOAD_ERROR.lock().unwrap().take()
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, error: std::io::Error },
+    Parse { path: PathBuf, message: String },
+    Invalid { key: String, value: String, reason: String },
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self,f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => write!(f,"could not read {}: {}",path.display(),error),
+            ConfigError::Parse { path, message } => write!(f,"could not parse {}: {}",path.display(),message),
+            ConfigError::Invalid { key, value, reason } => write!(f,"invalid value {:?} for `{}`: {}",value,key,reason),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
+
+// Used when Unity's requested adapter can't be matched by LUID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterPolicy {
+    HighPerformance,
+    LowPower,
+    Index(usize),
+    Name(String),
+}
+impl AdapterPolicy {
+    fn parse(key: &str,value: &str) -> Result<AdapterPolicy,ConfigError> {
+        let invalid = |reason: &str| ConfigError::Invalid { key: key.to_string(), value: value.to_string(), reason: reason.to_string() };
+        match value.split_once(':') {
+            None if value == "high-performance" => Ok(AdapterPolicy::HighPerformance),
+            None if value == "low-power" => Ok(AdapterPolicy::LowPower),
+            None if value == "first" => Ok(AdapterPolicy::Index(0)),
+            Some(("index", index)) => index.trim().parse().map(AdapterPolicy::Index).map_err(|_| invalid("expected `index:<number>`")),
+            Some(("name", name)) if !name.trim().is_empty() => Ok(AdapterPolicy::Name(name.trim().to_string())),
+            _ => Err(invalid("expected `high-performance`, `low-power`, `first`, `index:<number>` or `name:<substring>`")),
+        }
+    }
+}
+
+// Unset limits are derived from the kernels' reflected bindings; a value here overrides that.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitsConfig {
+    pub max_storage_buffers_per_shader_stage: Option<u32>,
+    pub max_acceleration_structures_per_shader_stage: Option<u32>,
+    pub max_storage_textures_per_shader_stage: Option<u32>,
+}
+
+// Each switch only turns things on; whatever wgpu enables by default or through WGPU_* variables stays on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DebugConfig {
+    pub debug_layer: bool,
+    pub gpu_based_validation: bool,
+    pub dred: bool,
+    pub wgpu_validation: bool,
+}
+
+// Kernels found under `dir` (same main/... and Utility/... layout as src/shaders) replace the embedded ones.
+// With `hot_reload` the directory is watched and changed kernels are rebuilt before their next dispatch.
+// `cache_dir` holds translated HLSL, its DXIL and the pipeline cache between runs.
+// With `lazy` only reflection runs at init; kernels are compiled on first dispatch or ComputeShader_Prewarm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadersConfig {
+    pub dir: Option<PathBuf>,
+    pub hot_reload: bool,
+    pub cache_dir: Option<PathBuf>,
+    pub lazy: bool,
+}
+
+// Percentages of the adapter's VRAM budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryConfig {
+    pub warn_percent: u8,
+    pub critical_percent: u8,
+    // Passed to wgpu, which then refuses to create resources past this point instead of letting the driver page.
+    pub wgpu_creation_threshold: Option<u8>,
+}
+
+// Extra CreateGfxDevice signature for UnityPlayer builds the built-in table doesn't know, tried before it.
+// The pattern must start at the function's first byte.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HookConfig {
+    pub create_gfx_device_pattern: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub native_log: PathBuf,
+    pub filter: String,
+    pub max_size: u64,
+    pub max_files: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CrashConfig {
+    pub dir: PathBuf,
+    pub history: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub adapter: AdapterPolicy,
+    pub dxc_path: String,
+    pub max_shader_model: DxcShaderModel,
+    pub forced_renderer: i32,
+    pub limits: LimitsConfig,
+    pub debug: DebugConfig,
+    pub memory: MemoryConfig,
+    pub shaders: ShadersConfig,
+    pub hook: HookConfig,
+    pub log: LogConfig,
+    pub crash: CrashConfig,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            adapter: AdapterPolicy::HighPerformance,
+            dxc_path: String::from("dxcompiler.dll"),
+            max_shader_model: DxcShaderModel::V6_7,
+            forced_renderer: 0x12,
+            limits: LimitsConfig::default(),
+            debug: DebugConfig::default(),
+            memory: MemoryConfig {
+                warn_percent: 80,
+                critical_percent: 95,
+                wgpu_creation_threshold: None,
+            },
+            shaders: ShadersConfig {
+                dir: Some(PathBuf::from("truetrace_shaders")),
+                hot_reload: false,
+                cache_dir: Some(PathBuf::from("truetrace_shader_cache")),
+                lazy: false,
+            },
+            hook: HookConfig::default(),
+            log: LogConfig {
+                native_log: PathBuf::from("truetrace_nativelog.txt"),
+                filter: String::from("info"),
+                max_size: 8 * 1024 * 1024,
+                max_files: 3,
+            },
+            crash: CrashConfig {
+                dir: PathBuf::from("."),
+                history: 64,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    adapter: Option<String>,
+    forced_renderer: Option<i64>,
+    #[serde(default)]
+    dxc: RawDxc,
+    #[serde(default)]
+    limits: RawLimits,
+    #[serde(default)]
+    debug: RawDebug,
+    #[serde(default)]
+    memory: RawMemory,
+    #[serde(default)]
+    shaders: RawShaders,
+    #[serde(default)]
+    hook: RawHook,
+    #[serde(default)]
+    log: RawLog,
+    #[serde(default)]
+    crash: RawCrash,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDxc {
+    path: Option<String>,
+    max_shader_model: Option<String>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLimits {
+    max_storage_buffers_per_shader_stage: Option<i64>,
+    max_acceleration_structures_per_shader_stage: Option<i64>,
+    max_storage_textures_per_shader_stage: Option<i64>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawDebug {
+    debug_layer: Option<bool>,
+    gpu_based_validation: Option<bool>,
+    dred: Option<bool>,
+    wgpu_validation: Option<bool>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawMemory {
+    warn_percent: Option<i64>,
+    critical_percent: Option<i64>,
+    wgpu_creation_threshold: Option<i64>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawShaders {
+    dir: Option<String>,
+    hot_reload: Option<bool>,
+    cache_dir: Option<String>,
+    lazy: Option<bool>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawHook {
+    create_gfx_device_pattern: Option<String>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawLog {
+    native_log: Option<String>,
+    filter: Option<String>,
+    max_size: Option<i64>,
+    max_files: Option<i64>,
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCrash {
+    dir: Option<String>,
+    history: Option<i64>,
+}
+
+fn env_string(var: &str,target: &mut Option<String>) {
+    if let Ok(value) = std::env::var(var) {
+        target.replace(value);
+    }
+}
+fn env_int(var: &str,target: &mut Option<i64>) -> Result<(),ConfigError> {
+    if let Ok(value) = std::env::var(var) {
+        let trimmed = value.trim();
+        let parsed = match trimmed.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex,16),
+            None => trimmed.parse(),
+        };
+        target.replace(parsed.map_err(|_| ConfigError::Invalid { key: var.to_string(), value: value.clone(), reason: String::from("expected an integer") })?);
+    }
+    Ok(())
+}
+fn env_bool(var: &str,target: &mut Option<bool>) -> Result<(),ConfigError> {
+    if let Ok(value) = std::env::var(var) {
+        let parsed = match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "on" | "yes" => true,
+            "0" | "false" | "off" | "no" => false,
+            _ => return Err(ConfigError::Invalid { key: var.to_string(), value: value.clone(), reason: String::from("expected 1/0, true/false or on/off") }),
+        };
+        target.replace(parsed);
+    }
+    Ok(())
+}
+fn to_u32(key: &str,value: i64) -> Result<u32,ConfigError> {
+    u32::try_from(value).map_err(|_| ConfigError::Invalid { key: key.to_string(), value: value.to_string(), reason: String::from("expected a non-negative 32-bit integer") })
+}
+fn to_percent(key: &str,value: i64) -> Result<u8,ConfigError> {
+    u8::try_from(value).ok().filter(|percent| (1..=100).contains(percent))
+        .ok_or_else(|| ConfigError::Invalid { key: key.to_string(), value: value.to_string(), reason: String::from("expected a percentage between 1 and 100") })
+}
+fn parse_shader_model(key: &str,value: &str) -> Result<DxcShaderModel,ConfigError> {
+    Ok(match value.trim() {
+        "6.0" => DxcShaderModel::V6_0,
+        "6.1" => DxcShaderModel::V6_1,
+        "6.2" => DxcShaderModel::V6_2,
+        "6.3" => DxcShaderModel::V6_3,
+        "6.4" => DxcShaderModel::V6_4,
+        "6.5" => DxcShaderModel::V6_5,
+        "6.6" => DxcShaderModel::V6_6,
+        "6.7" => DxcShaderModel::V6_7,
+        _ => return Err(ConfigError::Invalid { key: key.to_string(), value: value.to_string(), reason: String::from("expected a shader model between 6.0 and 6.7") }),
+    })
+}
+
+impl Config {
+    pub fn default_path() -> PathBuf {
+        if let Ok(path) = std::env::var("TRUETRACE_CONFIG") {
+            return PathBuf::from(path);
+        }
+        std::env::current_exe().ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(CONFIG_FILE_NAME)))
+            .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_NAME))
+    }
+    pub fn load_or_default() -> Config {
+        let path = Config::default_path();
+        match Config::load(&path) {
+            Ok(config) => config,
+            Err(err) => {
+                LOAD_ERROR.lock().unwrap().replace(err.to_string());
+                Config::default()
+            }
+        }
+    }
+    // A missing file is not an error; environment overrides still apply on top of the defaults.
+    pub fn load(path: &Path) -> Result<Config,ConfigError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(ConfigError::Io { path: path.to_path_buf(), error }),
+        };
+        Config::from_toml(&contents,path)
+    }
+    pub fn from_toml(contents: &str,path: &Path) -> Result<Config,ConfigError> {
+        let mut raw: RawConfig = toml::from_str(contents).map_err(|e| ConfigError::Parse { path: path.to_path_buf(), message: e.to_string() })?;
+        env_string("TRUETRACE_ADAPTER",&mut raw.adapter);
+        env_int("TRUETRACE_FORCED_RENDERER",&mut raw.forced_renderer)?;
+        env_string("TRUETRACE_DXC_PATH",&mut raw.dxc.path);
+        env_string("TRUETRACE_MAX_SHADER_MODEL",&mut raw.dxc.max_shader_model);
+        env_int("TRUETRACE_MAX_STORAGE_BUFFERS",&mut raw.limits.max_storage_buffers_per_shader_stage)?;
+        env_int("TRUETRACE_MAX_ACCELERATION_STRUCTURES",&mut raw.limits.max_acceleration_structures_per_shader_stage)?;
+        env_int("TRUETRACE_MAX_STORAGE_TEXTURES",&mut raw.limits.max_storage_textures_per_shader_stage)?;
+        env_bool("TRUETRACE_DEBUG_LAYER",&mut raw.debug.debug_layer)?;
+        env_bool("TRUETRACE_GPU_BASED_VALIDATION",&mut raw.debug.gpu_based_validation)?;
+        env_bool("TRUETRACE_DRED",&mut raw.debug.dred)?;
+        env_bool("TRUETRACE_WGPU_VALIDATION",&mut raw.debug.wgpu_validation)?;
+        env_int("TRUETRACE_MEMORY_WARN_PERCENT",&mut raw.memory.warn_percent)?;
+        env_int("TRUETRACE_MEMORY_CRITICAL_PERCENT",&mut raw.memory.critical_percent)?;
+        env_int("TRUETRACE_WGPU_CREATION_THRESHOLD",&mut raw.memory.wgpu_creation_threshold)?;
+        env_string("TRUETRACE_SHADER_DIR",&mut raw.shaders.dir);
+        env_bool("TRUETRACE_SHADER_HOT_RELOAD",&mut raw.shaders.hot_reload)?;
+        env_string("TRUETRACE_SHADER_CACHE_DIR",&mut raw.shaders.cache_dir);
+        env_bool("TRUETRACE_SHADER_LAZY",&mut raw.shaders.lazy)?;
+        env_string("TRUETRACE_CREATE_GFX_DEVICE_PATTERN",&mut raw.hook.create_gfx_device_pattern);
+        env_string("TRUETRACE_NATIVE_LOG",&mut raw.log.native_log);
+        env_string("TRUETRACE_LOG",&mut raw.log.filter);
+        env_int("TRUETRACE_LOG_MAX_SIZE",&mut raw.log.max_size)?;
+        env_int("TRUETRACE_LOG_MAX_FILES",&mut raw.log.max_files)?;
+        env_string("TRUETRACE_CRASH_DIR",&mut raw.crash.dir);
+        env_int("TRUETRACE_CRASH_HISTORY",&mut raw.crash.history)?;
+        Config::from_raw(raw)
+    }
+    fn from_raw(raw: RawConfig) -> Result<Config,ConfigError> {
+        let mut config = Config::default();
+        if let Some(adapter) = raw.adapter {
+            config.adapter = AdapterPolicy::parse("adapter",&adapter)?;
+        }
+        if let Some(renderer) = raw.forced_renderer {
+            config.forced_renderer = i32::try_from(renderer).map_err(|_| ConfigError::Invalid { key: String::from("forced_renderer"), value: renderer.to_string(), reason: String::from("expected a 32-bit renderer id") })?;
+        }
+        if let Some(path) = raw.dxc.path {
+            if path.trim().is_empty() {
+                return Err(ConfigError::Invalid { key: String::from("dxc.path"), value: path, reason: String::from("must not be empty") });
+            }
+            config.dxc_path = path;
+        }
+        if let Some(model) = raw.dxc.max_shader_model {
+            config.max_shader_model = parse_shader_model("dxc.max_shader_model",&model)?;
+        }
+        if let Some(value) = raw.limits.max_storage_buffers_per_shader_stage {
+            config.limits.max_storage_buffers_per_shader_stage = Some(to_u32("limits.max_storage_buffers_per_shader_stage",value)?);
+        }
+        if let Some(value) = raw.limits.max_acceleration_structures_per_shader_stage {
+            config.limits.max_acceleration_structures_per_shader_stage = Some(to_u32("limits.max_acceleration_structures_per_shader_stage",value)?);
+        }
+        if let Some(value) = raw.limits.max_storage_textures_per_shader_stage {
+            config.limits.max_storage_textures_per_shader_stage = Some(to_u32("limits.max_storage_textures_per_shader_stage",value)?);
+        }
+        config.debug = DebugConfig {
+            debug_layer: raw.debug.debug_layer.unwrap_or(false),
+            gpu_based_validation: raw.debug.gpu_based_validation.unwrap_or(false),
+            dred: raw.debug.dred.unwrap_or(false),
+            wgpu_validation: raw.debug.wgpu_validation.unwrap_or(false),
+        };
+        if let Some(percent) = raw.memory.warn_percent {
+            config.memory.warn_percent = to_percent("memory.warn_percent",percent)?;
+        }
+        if let Some(percent) = raw.memory.critical_percent {
+            config.memory.critical_percent = to_percent("memory.critical_percent",percent)?;
+        }
+        if config.memory.warn_percent > config.memory.critical_percent {
+            return Err(ConfigError::Invalid { key: String::from("memory.warn_percent"), value: config.memory.warn_percent.to_string(), reason: format!("must not be above memory.critical_percent ({})",config.memory.critical_percent) });
+        }
+        if let Some(percent) = raw.memory.wgpu_creation_threshold {
+            config.memory.wgpu_creation_threshold = Some(to_percent("memory.wgpu_creation_threshold",percent)?);
+        }
+        if let Some(dir) = raw.shaders.dir {
+            // An empty value turns the override directory off.
+            config.shaders.dir = (!dir.trim().is_empty()).then(|| PathBuf::from(dir));
+        }
+        config.shaders.hot_reload = raw.shaders.hot_reload.unwrap_or(false);
+        config.shaders.lazy = raw.shaders.lazy.unwrap_or(false);
+        if let Some(dir) = raw.shaders.cache_dir {
+            // An empty value turns the cache off.
+            config.shaders.cache_dir = (!dir.trim().is_empty()).then(|| PathBuf::from(dir));
+        }
+        if let Some(pattern) = raw.hook.create_gfx_device_pattern.filter(|pattern| !pattern.trim().is_empty()) {
+            crate::sigscan::Pattern::parse(&pattern).map_err(|reason| ConfigError::Invalid { key: String::from("hook.create_gfx_device_pattern"), value: pattern.clone(), reason })?;
+            config.hook.create_gfx_device_pattern = Some(pattern);
+        }
+        if let Some(path) = raw.log.native_log {
+            config.log.native_log = PathBuf::from(path);
+        }
+        if let Some(filter) = raw.log.filter {
+            crate::logging::Filter::parse(&filter).map_err(|reason| ConfigError::Invalid { key: String::from("log.filter"), value: filter.clone(), reason })?;
+            config.log.filter = filter;
+        }
+        if let Some(size) = raw.log.max_size {
+            config.log.max_size = u64::try_from(size).map_err(|_| ConfigError::Invalid { key: String::from("log.max_size"), value: size.to_string(), reason: String::from("expected a size in bytes") })?;
+        }
+        if let Some(files) = raw.log.max_files {
+            config.log.max_files = to_u32("log.max_files",files)?;
+        }
+        if let Some(dir) = raw.crash.dir {
+            config.crash.dir = PathBuf::from(dir);
+        }
+        if let Some(history) = raw.crash.history {
+            config.crash.history = to_u32("crash.history",history)? as usize;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from_toml reads the process environment, so tests that go through it take turns.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn parse(contents: &str) -> Result<Config,ConfigError> {
+        let _env = ENV.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        Config::from_toml(contents,Path::new("truetrace.toml"))
+    }
+    fn invalid_key(result: Result<Config,ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("expected an invalid value, got {:?}",other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn the_example_file_parses() {
+        let config = parse(include_str!("../truetrace.example.toml")).unwrap();
+        assert_eq!(config.adapter,AdapterPolicy::HighPerformance);
+        assert_eq!(config.memory,Config::default().memory);
+        assert_eq!(config.log.max_size,8 * 1024 * 1024);
+    }
+
+    #[test]
+    fn reads_values_from_the_file() {
+        let config = parse("adapter = \"name:Radeon\"\n[dxc]\nmax_shader_model = \"6.5\"\n[limits]\nmax_storage_buffers_per_shader_stage = 40\n[memory]\nwarn_percent = 60\ncritical_percent = 90\n[shaders]\ndir = \"\"\n").unwrap();
+        assert_eq!(config.adapter,AdapterPolicy::Name(String::from("Radeon")));
+        assert!(matches!(config.max_shader_model,DxcShaderModel::V6_5));
+        assert_eq!(config.limits.max_storage_buffers_per_shader_stage,Some(40));
+        assert_eq!((config.memory.warn_percent,config.memory.critical_percent),(60,90));
+        assert_eq!(config.shaders.dir,None);
+    }
+
+    #[test]
+    fn rejects_out_of_range_percentages() {
+        assert_eq!(invalid_key(parse("[memory]\nwarn_percent = 0\n")),"memory.warn_percent");
+        assert_eq!(invalid_key(parse("[memory]\ncritical_percent = 101\n")),"memory.critical_percent");
+        assert_eq!(invalid_key(parse("[memory]\nwgpu_creation_threshold = -5\n")),"memory.wgpu_creation_threshold");
+        assert!(parse("[memory]\nwarn_percent = 1\ncritical_percent = 100\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_warn_above_critical() {
+        assert_eq!(invalid_key(parse("[memory]\nwarn_percent = 90\ncritical_percent = 85\n")),"memory.warn_percent");
+        // Against the default critical_percent of 95.
+        assert_eq!(invalid_key(parse("[memory]\nwarn_percent = 96\n")),"memory.warn_percent");
+        assert!(parse("[memory]\nwarn_percent = 90\ncritical_percent = 90\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_shader_models() {
+        for model in ["6.8", "5.1", "6", ""] {
+            assert_eq!(invalid_key(parse(&format!("[dxc]\nmax_shader_model = {:?}\n",model))),"dxc.max_shader_model");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        for contents in ["adaptor = \"first\"\n", "[memory]\nwarn = 50\n", "[renderer]\nforced = 1\n"] {
+            assert!(matches!(parse(contents),Err(ConfigError::Parse { .. })),"{:?}",contents);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_adapter_policies() {
+        assert_eq!(invalid_key(parse("adapter = \"fastest\"\n")),"adapter");
+        assert_eq!(invalid_key(parse("adapter = \"index:two\"\n")),"adapter");
+        assert_eq!(invalid_key(parse("adapter = \"name: \"\n")),"adapter");
+        assert_eq!(parse("adapter = \"index: 2\"\n").unwrap().adapter,AdapterPolicy::Index(2));
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let _env = ENV.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        let file = "adapter = \"low-power\"\nforced_renderer = 2\n[memory]\nwarn_percent = 60\n[shaders]\nlazy = false\n";
+        let vars = [
+            ("TRUETRACE_ADAPTER","index:1"),
+            ("TRUETRACE_FORCED_RENDERER","0x12"),
+            ("TRUETRACE_MEMORY_WARN_PERCENT","70"),
+            ("TRUETRACE_SHADER_LAZY","on"),
+        ];
+        for (var,value) in vars {
+            unsafe {std::env::set_var(var,value)};
+        }
+        let config = Config::from_toml(file,Path::new("truetrace.toml"));
+        unsafe {std::env::set_var("TRUETRACE_SHADER_LAZY","maybe")};
+        let bad_bool = Config::from_toml(file,Path::new("truetrace.toml"));
+        for (var,_) in vars {
+            unsafe {std::env::remove_var(var)};
+        }
+        let config = config.unwrap();
+        assert_eq!(config.adapter,AdapterPolicy::Index(1));
+        assert_eq!(config.forced_renderer,0x12);
+        assert_eq!(config.memory.warn_percent,70);
+        assert!(config.shaders.lazy);
+        assert!(matches!(bad_bool,Err(ConfigError::Invalid { key, .. }) if key == "TRUETRACE_SHADER_LAZY"));
+    }
+}
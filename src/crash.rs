@@ -0,0 +1,129 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::{Mutex, Once};
+use chrono::{DateTime, Local};
+use wgpu::AdapterInfo;
+use crate::config::CONFIG;
+
+#[derive(Debug, Clone)]
+pub struct InteropCall {
+    pub time: DateTime<Local>,
+    pub call: &'static str,
+    pub kernel: String,
+    pub dispatch: Option<[u32;3]>,
+    pub resources: Vec<String>,
+}
+impl InteropCall {
+    pub fn new(call: &'static str,kernel: impl Into<String>) -> InteropCall {
+        InteropCall { time: Local::now(), call, kernel: kernel.into(), dispatch: None, resources: Vec::new() }
+    }
+}
+
+struct CrashContext {
+    history: VecDeque<InteropCall>,
+    capacity: usize,
+    adapter: Option<String>,
+    config: String,
+    dir: PathBuf,
+}
+static CONTEXT: Mutex<CrashContext> = Mutex::new(CrashContext {
+    history: VecDeque::new(),
+    capacity: 0,
+    adapter: None,
+    config: String::new(),
+    dir: PathBuf::new(),
+});
+static INSTALL: Once = Once::new();
+
+pub fn record(call: InteropCall) {
+    let mut context = CONTEXT.lock().unwrap();
+    if context.capacity == 0 {
+        return;
+    }
+    while context.history.len() >= context.capacity {
+        context.history.pop_front();
+    }
+    context.history.push_back(call);
+}
+
+pub fn set_adapter_info(info: &AdapterInfo) {
+    CONTEXT.lock().unwrap().adapter.replace(format!("{:#?}",info));
+}
+
+fn payload_text(payload: &(dyn Any + Send)) -> &str {
+    if let Some(text) = payload.downcast_ref::<&str>() {
+        text
+    } else if let Some(text) = payload.downcast_ref::<String>() {
+        text.as_str()
+    } else {
+        "<non-string panic payload>"
+    }
+}
+
+fn build_report(info: &PanicHookInfo,context: Option<&CrashContext>) -> String {
+    let thread = std::thread::current();
+    let mut report = String::new();
+    let _ = writeln!(report,"TrueTrace native crash report");
+    let _ = writeln!(report,"Time: {}",Local::now().format("%Y-%m-%d %H:%M:%S%.3f"));
+    let _ = writeln!(report,"Thread: {} ({:?})",thread.name().unwrap_or("<unnamed>"),thread.id());
+    let _ = writeln!(report,"Panic: {}",payload_text(info.payload()));
+    match info.location() {
+        Some(location) => { let _ = writeln!(report,"Location: {}:{}:{}",location.file(),location.line(),location.column()); },
+        None => { let _ = writeln!(report,"Location: <unknown>"); },
+    }
+    let _ = writeln!(report,"\nBacktrace:\n{}",Backtrace::force_capture());
+    // The lock may be held by the panicking thread itself; report what we can instead of deadlocking.
+    let Some(context) = context else {
+        let _ = writeln!(report,"\nCrash context unavailable (lock held at panic time)");
+        return report;
+    };
+    let _ = writeln!(report,"\nAdapter:\n{}",context.adapter.as_deref().unwrap_or("<no device created>"));
+    let _ = writeln!(report,"\nConfig:\n{}",context.config);
+    let _ = writeln!(report,"\nLast {} interop calls (oldest first):",context.history.len());
+    for call in &context.history {
+        let _ = write!(report,"  {} {} {}",call.time.format("%H:%M:%S%.3f"),call.call,call.kernel);
+        if let Some([x,y,z]) = call.dispatch {
+            let _ = write!(report," ({}, {}, {})",x,y,z);
+        }
+        let _ = writeln!(report);
+        for resource in &call.resources {
+            let _ = writeln!(report,"      {}",resource);
+        }
+    }
+    report
+}
+
+fn write_report(info: &PanicHookInfo) {
+    let guard = CONTEXT.try_lock().ok();
+    let report = build_report(info,guard.as_deref());
+    let dir = guard.as_ref().map_or_else(PathBuf::new,|context| context.dir.clone());
+    drop(guard);
+    let path = dir.join(format!("truetrace_crash_{}.txt",Local::now().format("%Y%m%d-%H%M%S%.3f")));
+    let written = std::fs::create_dir_all(if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir })
+        .and_then(|_| std::fs::File::create(&path))
+        .and_then(|mut file| file.write_all(report.as_bytes()));
+    // Not log::error!: the logger's locks or Unity's callback may be what panicked.
+    let message = match written {
+        Ok(()) => format!("Panic: {} (crash report written to {})",payload_text(info.payload()),path.display()),
+        Err(err) => format!("Panic: {} (could not write crash report to {}: {})\n{}",payload_text(info.payload()),path.display(),err,report),
+    };
+    crate::logging::try_log_error(module_path!(),&message);
+}
+
+pub fn install_hook() {
+    INSTALL.call_once(|| {
+        let config = CONFIG.get_mut().clone();
+        {
+            let mut context = CONTEXT.lock().unwrap();
+            context.capacity = config.crash.history;
+            context.config = format!("{:#?}",config);
+            context.dir = config.crash.dir.clone();
+        }
+        std::panic::set_hook(Box::new(write_report));
+    });
+}
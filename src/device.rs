@@ -0,0 +1,273 @@
+use std::ffi::{c_char, CString};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use wgpu::{Adapter, Backend, BackendOptions, Backends, Device, DeviceLostReason, Dx12BackendOptions, Dx12Compiler, Features, InstanceDescriptor, InstanceFlags, MemoryBudgetThresholds, Queue, RequestDeviceError};
+use wgpu::wgt::DeviceDescriptor;
+use crate::config::{DebugConfig, CONFIG};
+use crate::limits::derive_limits;
+use crate::{crash, ADAPTER, ADAPTER_INFO, DEVICE, INSTANCE};
+
+pub const DEVICE_STATUS_UNINITIALISED: u32 = 0;
+pub const DEVICE_STATUS_READY: u32 = 1;
+pub const DEVICE_STATUS_LOST: u32 = 2;
+pub const DEVICE_STATUS_RECOVERING: u32 = 3;
+
+pub const CAPABILITY_RAY_QUERY: u32 = 1;
+pub const CAPABILITY_PASSTHROUGH_SHADERS: u32 = 2;
+// Set when kernels that need ray query were swapped for their software-BVH builds.
+pub const CAPABILITY_SOFTWARE_BVH: u32 = 4;
+
+static STATUS: AtomicU32 = AtomicU32::new(DEVICE_STATUS_UNINITIALISED);
+// Bumped for every device we hand out, so lost callbacks and ComputeShader handles from an older one can be told apart.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static CAPABILITIES: AtomicU32 = AtomicU32::new(0);
+
+// Called with DEVICE_STATUS_LOST when the device goes away and DEVICE_STATUS_READY once a replacement is up.
+// It usually runs on the runtime thread, so queue TrueTrace_RecoverDevice for later instead of calling it from here.
+pub type DeviceStatusCallback = extern "C" fn(status: u32,message: *const c_char);
+static STATUS_CALLBACK: Mutex<Option<DeviceStatusCallback>> = Mutex::new(None);
+
+fn instance_flags(debug: &DebugConfig) -> InstanceFlags {
+    let mut flags = InstanceFlags::from_env_or_default();
+    if debug.debug_layer {
+        flags |= InstanceFlags::VALIDATION;
+    }
+    if debug.gpu_based_validation {
+        flags |= InstanceFlags::VALIDATION | InstanceFlags::GPU_BASED_VALIDATION;
+    }
+    if debug.wgpu_validation {
+        flags |= InstanceFlags::DEBUG | InstanceFlags::VALIDATION_INDIRECT_CALL;
+    }
+    if flags != InstanceFlags::from_env_or_default() {
+        log::info!("Instance flags from config: {:?}",flags);
+    }
+    flags
+}
+pub fn create_instance(backends: Backends) -> wgpu::Instance {
+    let config = CONFIG.get_mut().clone();
+    let flags = instance_flags(&config.debug);
+    #[cfg(windows)]
+    if config.debug.dred && backends.contains(Backends::DX12) {
+        crate::dred::enable();
+    }
+    wgpu::Instance::new(&InstanceDescriptor {
+        backends,
+        flags,
+        memory_budget_thresholds: MemoryBudgetThresholds {
+            for_resource_creation: config.memory.wgpu_creation_threshold,
+            for_device_loss: None,
+        },
+        backend_options: BackendOptions {
+            dx12: Dx12BackendOptions {
+                shader_compiler: Dx12Compiler::DynamicDxc {
+                    dxc_path: config.dxc_path,
+                    max_shader_model: config.max_shader_model,
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    })
+}
+pub fn wanted_features(backend: Backend) -> Features {
+    match backend {
+        Backend::Dx12 => Features::EXPERIMENTAL_RAY_QUERY | Features::EXPERIMENTAL_PASSTHROUGH_SHADERS,
+        // Pipeline caches are Vulkan-only in wgpu.
+        Backend::Vulkan => Features::EXPERIMENTAL_RAY_QUERY | Features::EXPERIMENTAL_PASSTHROUGH_SHADERS | Features::PIPELINE_CACHE,
+        _ => Features::EXPERIMENTAL_RAY_QUERY,
+    }
+}
+// Only ask for what the adapter has; whatever is missing is reported and worked around instead of failing the request.
+pub fn negotiate_features(adapter: &Adapter) -> Features {
+    let wanted = wanted_features(adapter.get_info().backend);
+    let supported = wanted & adapter.features();
+    let missing = wanted - supported;
+    if !missing.is_empty() {
+        log::warn!("Adapter lacks {:?}, falling back where possible",missing);
+    }
+    supported
+}
+fn capabilities_from(features: Features) -> u32 {
+    let mut capabilities = 0;
+    if features.contains(Features::EXPERIMENTAL_RAY_QUERY) {
+        capabilities |= CAPABILITY_RAY_QUERY;
+    }
+    if features.contains(Features::EXPERIMENTAL_PASSTHROUGH_SHADERS) {
+        capabilities |= CAPABILITY_PASSTHROUGH_SHADERS;
+    }
+    capabilities
+}
+pub async fn request_device(adapter: &Adapter) -> Result<(Device,Queue),RequestDeviceError> {
+    let overrides = CONFIG.get_mut().limits;
+    let (device,queue) = adapter.request_device(&DeviceDescriptor {
+        label: None,
+        required_features: negotiate_features(adapter),
+        required_limits: derive_limits(&crate::render::kernel_binding_counts(),&adapter.limits(),&overrides),
+        ..Default::default()
+    }).await?;
+    device.on_uncaptured_error(Box::new(|error| {
+        log::error!(target: "wgpu", "Uncaptured wgpu error: {}",error);
+    }));
+    CAPABILITIES.store(capabilities_from(device.features()),Ordering::SeqCst);
+    let generation = GENERATION.load(Ordering::SeqCst) + 1;
+    #[cfg(windows)]
+    let dred = CONFIG.get_mut().debug.dred.then(|| crate::dred::raw_device(&device)).flatten();
+    device.set_device_lost_callback(move |reason,message| {
+        // Destroyed is us dropping the device on purpose (recovery or shutdown), not a driver reset.
+        if reason == DeviceLostReason::Destroyed || generation != GENERATION.load(Ordering::SeqCst) {
+            log::debug!("Device generation {} released: {}",generation,message);
+            return;
+        }
+        mark_lost(&message,|| {
+            #[cfg(windows)]
+            if let Some(raw) = &dred {
+                crate::dred::log_report(raw);
+            }
+        });
+    }));
+    Ok((device,queue))
+}
+
+pub fn status() -> u32 {
+    STATUS.load(Ordering::SeqCst)
+}
+pub fn is_ready() -> bool {
+    status() == DEVICE_STATUS_READY
+}
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+pub fn capabilities() -> u32 {
+    CAPABILITIES.load(Ordering::SeqCst)
+}
+pub fn has_capability(capability: u32) -> bool {
+    capabilities() & capability != 0
+}
+pub(crate) fn add_capability(capability: u32) {
+    CAPABILITIES.fetch_or(capability,Ordering::SeqCst);
+}
+
+fn notify(status: u32,message: &str) {
+    let callback = *STATUS_CALLBACK.lock().unwrap();
+    if let Some(callback) = callback {
+        let message = CString::new(message.replace('\0',"")).unwrap_or_default();
+        callback(status,message.as_ptr());
+    }
+}
+
+// Runs from the device-lost callback, which can fire inside queue.submit or poll while DEVICE is locked, so nothing
+// here may lock DEVICE; `report` logs the DRED data from a handle captured up front.
+fn mark_lost(message: &str,report: impl FnOnce()) {
+    if STATUS.swap(DEVICE_STATUS_LOST,Ordering::SeqCst) == DEVICE_STATUS_LOST {
+        return;
+    }
+    log::error!("GPU device lost: {}",message);
+    report();
+    // Under Unity the game recreates its D3D12 device after a reset, so let that call come back through us.
+    #[cfg(windows)]
+    crate::shim::rearm_create_device();
+    notify(DEVICE_STATUS_LOST,message);
+}
+
+// Publishes a freshly requested device. If it replaces a lost one, the kernel registry is rebuilt against it
+// and C# is told to reload its ComputeShader handles.
+pub fn install(adapter: Adapter,device: Device,queue: Queue) {
+    let info = adapter.get_info();
+    crash::set_adapter_info(&info);
+    ADAPTER_INFO.get_mut().replace(info);
+    ADAPTER.get_mut().replace(adapter);
+    GENERATION.fetch_add(1,Ordering::SeqCst);
+    crate::shader_cache::open_pipeline_cache(&device,ADAPTER_INFO.get_mut().as_ref().unwrap());
+    crate::runtime::start_polling(device.clone());
+    DEVICE.get_mut().replace((device,queue));
+    let previous = STATUS.swap(DEVICE_STATUS_READY,Ordering::SeqCst);
+    if previous == DEVICE_STATUS_LOST || previous == DEVICE_STATUS_RECOVERING {
+        crate::render::rebuild_kernels();
+        log::info!("GPU device recovered (generation {})",generation());
+        notify(DEVICE_STATUS_READY,"device recovered, reload compute shaders");
+    }
+}
+
+pub fn recover() -> Result<(),String> {
+    if STATUS.compare_exchange(DEVICE_STATUS_LOST,DEVICE_STATUS_RECOVERING,Ordering::SeqCst,Ordering::SeqCst).is_err() {
+        return Err(String::from("device is not lost"));
+    }
+    let Some(adapter) = ADAPTER.get_mut().take() else {
+        STATUS.store(DEVICE_STATUS_LOST,Ordering::SeqCst);
+        return Err(String::from("no adapter to recover on"));
+    };
+    crate::render::release_kernels();
+    crate::shader_cache::close_pipeline_cache(false);
+    DEVICE.get_mut().take();
+    match crate::runtime::block_on(request_device(&adapter)) {
+        Ok((device,queue)) => {
+            install(adapter,device,queue);
+            Ok(())
+        },
+        Err(err) => {
+            ADAPTER.get_mut().replace(adapter);
+            STATUS.store(DEVICE_STATUS_LOST,Ordering::SeqCst);
+            Err(err.to_string())
+        }
+    }
+}
+
+// Hooks first so nothing new reaches us, then let the GPU finish, then drop kernels and live ComputeShader handles
+// before the device and instance they were created from. Safe to call more than once.
+// Waits on the GPU and joins threads, so it must not run under the loader lock; DllMain only flushes.
+pub fn shutdown() {
+    #[cfg(windows)]
+    crate::shim::remove_hooks();
+    #[cfg(windows)]
+    crate::shim::join_init_thread();
+    STATUS.store(DEVICE_STATUS_UNINITIALISED,Ordering::SeqCst);
+    crate::runtime::stop_polling();
+    crate::hot_reload::stop();
+    if let Some((device,_)) = DEVICE.get_mut().as_ref() {
+        if let Err(err) = device.poll(wgpu::PollType::Wait) {
+            log::warn!("Queue did not go idle before shutdown: {}",err);
+        }
+    }
+    crate::compute_shader_interop::release_live_shaders();
+    crate::render::release_kernels();
+    crate::shader_cache::close_pipeline_cache(true);
+    if DEVICE.get_mut().take().is_some() {
+        log::info!("Released the wgpu device");
+    }
+    ADAPTER.get_mut().take();
+    ADAPTER_INFO.get_mut().take();
+    INSTANCE.get_mut().take();
+    crate::runtime::shutdown(true);
+    log::logger().flush();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_GetDeviceStatus() -> u32 {
+    status()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_GetCapabilities() -> u32 {
+    capabilities()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_SetDeviceStatusCallback(callback: Option<DeviceStatusCallback>) {
+    *STATUS_CALLBACK.lock().unwrap() = callback;
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_Shutdown() {
+    shutdown();
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_RecoverDevice() -> bool {
+    match recover() {
+        Ok(()) => true,
+        Err(err) => {
+            log::error!("Device recovery failed: {}",err);
+            false
+        }
+    }
+}
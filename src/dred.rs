@@ -0,0 +1,55 @@
+use windows::core::Interface;
+use windows::Win32::Graphics::Direct3D12::{D3D12GetDebugInterface, ID3D12Device, ID3D12DeviceRemovedExtendedData, ID3D12DeviceRemovedExtendedDataSettings, D3D12_DRED_AUTO_BREADCRUMBS_OUTPUT, D3D12_DRED_ENABLEMENT_FORCED_ON, D3D12_DRED_PAGE_FAULT_OUTPUT};
+
+// Has to run before the D3D12 device is created.
+pub fn enable() {
+    let mut settings: Option<ID3D12DeviceRemovedExtendedDataSettings> = None;
+    match unsafe {D3D12GetDebugInterface(&mut settings)} {
+        Ok(()) => if let Some(settings) = settings {
+            unsafe {
+                settings.SetAutoBreadcrumbsEnablement(D3D12_DRED_ENABLEMENT_FORCED_ON);
+                settings.SetPageFaultEnablement(D3D12_DRED_ENABLEMENT_FORCED_ON);
+            }
+            log::info!("DRED breadcrumbs and page fault reporting enabled");
+        },
+        Err(err) => log::warn!("Could not enable DRED: {}",err),
+    }
+}
+
+// Taken when the device is created, since the report has to be read from the device-lost callback, which can run
+// while DEVICE is locked (inside queue.submit or poll). None on other backends.
+pub fn raw_device(device: &wgpu::Device) -> Option<ID3D12Device> {
+    let hal = unsafe {device.as_hal::<wgpu_hal::dx12::Api>()}?;
+    Some(hal.raw_device().clone())
+}
+
+// Logs where each command list stopped and the faulting address, if the driver recorded one.
+pub fn log_report(device: &ID3D12Device) {
+    let dred = match device.cast::<ID3D12DeviceRemovedExtendedData>() {
+        Ok(dred) => dred,
+        Err(err) => {
+            log::warn!("No DRED data: {}",err);
+            return;
+        }
+    };
+    let mut breadcrumbs = D3D12_DRED_AUTO_BREADCRUMBS_OUTPUT::default();
+    if unsafe {dred.GetAutoBreadcrumbsOutput(&mut breadcrumbs)}.is_ok() {
+        let mut node = breadcrumbs.pHeadAutoBreadcrumbNode;
+        while !node.is_null() {
+            let current = unsafe {&*node};
+            let name = unsafe {current.pCommandListDebugNameW.to_string()}.unwrap_or_default();
+            let completed = if current.pLastBreadcrumbValue.is_null() { 0 } else { unsafe {*current.pLastBreadcrumbValue} };
+            if completed < current.BreadcrumbCount {
+                let op = unsafe {*current.pCommandHistory.add(completed as usize)};
+                log::error!("DRED: command list {:?} stopped at operation {}/{} ({:?})",name,completed,current.BreadcrumbCount,op);
+            } else {
+                log::info!("DRED: command list {:?} completed all {} operations",name,current.BreadcrumbCount);
+            }
+            node = current.pNext;
+        }
+    }
+    let mut page_fault = D3D12_DRED_PAGE_FAULT_OUTPUT::default();
+    if unsafe {dred.GetPageFaultAllocationOutput(&mut page_fault)}.is_ok() && page_fault.PageFaultVA != 0 {
+        log::error!("DRED: page fault at GPU address {:#x}",page_fault.PageFaultVA);
+    }
+}
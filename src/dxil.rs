@@ -0,0 +1,56 @@
+use std::ffi::c_void;
+use std::sync::OnceLock;
+use windows::core::{Interface, GUID, HRESULT, HSTRING, PCWSTR};
+use windows::Win32::Graphics::Direct3D::Dxc::{CLSID_DxcCompiler, DxcBuffer, IDxcBlob, IDxcBlobUtf8, IDxcCompiler3, IDxcIncludeHandler, IDxcResult, DXC_CP_UTF8, DXC_OUT_ERRORS, DXC_OUT_OBJECT};
+use winapi::um::libloaderapi::{GetProcAddress, LoadLibraryW};
+use crate::config::CONFIG;
+
+type CreateInstance = unsafe extern "system" fn(*const GUID,*const GUID,*mut *mut c_void) -> HRESULT;
+
+// The same dxcompiler.dll wgpu is configured with, so the DXIL matches what it would have compiled itself.
+fn create_instance_proc() -> Result<CreateInstance,String> {
+    static PROC: OnceLock<Result<usize,String>> = OnceLock::new();
+    let addr = PROC.get_or_init(|| {
+        let path = CONFIG.get_mut().dxc_path.clone();
+        let wide = HSTRING::from(path.as_str());
+        let module = unsafe {LoadLibraryW(wide.as_ptr())};
+        if module.is_null() {
+            return Err(format!("could not load {}",path));
+        }
+        let addr = unsafe {GetProcAddress(module,c"DxcCreateInstance".as_ptr())} as usize;
+        if addr == 0 {
+            return Err(format!("{} has no DxcCreateInstance",path));
+        }
+        Ok(addr)
+    }).clone()?;
+    Ok(unsafe {std::mem::transmute::<usize,CreateInstance>(addr)})
+}
+
+// Compiler instances aren't thread safe, so every call makes its own; kernels are built on several threads.
+pub fn compile(hlsl: &str,args: &[&str]) -> Result<Vec<u8>,String> {
+    let create = create_instance_proc()?;
+    let mut raw = std::ptr::null_mut();
+    unsafe {create(&CLSID_DxcCompiler,&IDxcCompiler3::IID,&mut raw)}.ok().map_err(|e| format!("could not create the DXC compiler: {}",e))?;
+    let compiler = unsafe {IDxcCompiler3::from_raw(raw)};
+    let args = args.iter().map(|arg| HSTRING::from(*arg)).collect::<Vec<_>>();
+    let arg_ptrs = args.iter().map(|arg| PCWSTR(arg.as_ptr())).collect::<Vec<_>>();
+    let source = DxcBuffer {
+        Ptr: hlsl.as_ptr() as *const c_void,
+        Size: hlsl.len(),
+        Encoding: DXC_CP_UTF8.0,
+    };
+    let result: IDxcResult = unsafe {compiler.Compile(&source,Some(&arg_ptrs),None::<&IDxcIncludeHandler>)}.map_err(|e| e.to_string())?;
+    let status = unsafe {result.GetStatus()}.map_err(|e| e.to_string())?;
+    if status.is_err() {
+        let mut errors: Option<IDxcBlobUtf8> = None;
+        let _ = unsafe {result.GetOutput(DXC_OUT_ERRORS,&mut errors,std::ptr::null_mut())};
+        let message = errors.map(|blob| unsafe {
+            String::from_utf8_lossy(std::slice::from_raw_parts(blob.GetStringPointer().0,blob.GetStringLength())).into_owned()
+        });
+        return Err(message.unwrap_or_else(|| format!("DXC failed with {}",status)));
+    }
+    let mut object: Option<IDxcBlob> = None;
+    unsafe {result.GetOutput(DXC_OUT_OBJECT,&mut object,std::ptr::null_mut())}.map_err(|e| e.to_string())?;
+    let object = object.ok_or("DXC produced no object")?;
+    Ok(unsafe {std::slice::from_raw_parts(object.GetBufferPointer() as *const u8,object.GetBufferSize())}.to_vec())
+}
@@ -1,58 +1,203 @@
-use std::any::Any;
-use std::io::Write;
-use std::ptr::{addr_of, addr_of_mut};
-use winapi::shared::minwindef::{DWORD, LPVOID};
-use winapi::um::errhandlingapi::AddVectoredExceptionHandler;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+use retour::{Function, StaticDetour};
+use winapi::shared::minwindef::DWORD;
+use winapi::um::errhandlingapi::{AddVectoredExceptionHandler, RemoveVectoredExceptionHandler};
 use winapi::um::memoryapi::VirtualProtect;
 use winapi::um::minwinbase::EXCEPTION_BREAKPOINT;
-use winapi::um::winnt::{PAGE_EXECUTE_READWRITE, PVECTORED_EXCEPTION_HANDLER};
+use winapi::um::winnt::{PAGE_EXECUTE_READWRITE, PEXCEPTION_POINTERS};
 use winapi::vc::excpt::{EXCEPTION_CONTINUE_EXECUTION, EXCEPTION_CONTINUE_SEARCH};
-use crate::log_file;
-
-static mut orig: usize = 0;
-static mut hook: usize = 0;
-static mut og_byte: u8 = 0;
-pub struct Hook<T,H> {
-    orig: T,
-    hook: H,
-    original_byte: u8,
-    handle: *mut std::ffi::c_void,
-}
-impl<T,H> Hook<T,H> {
-    unsafe extern "system" fn exception_handler(info: winapi::um::winnt::PEXCEPTION_POINTERS) -> i32 {
-        log_file.get_mut().write("ExceptionHandler\n".as_bytes()).unwrap();
-        let record = (*info).ExceptionRecord;
-        let code = (*record).ExceptionCode;
-        let addr = (*record).ExceptionAddress;
-        if code == EXCEPTION_BREAKPOINT {
-            log_file.get_mut().write("Breakpoint!\n".as_bytes()).unwrap();
-            if addr == orig as _ {
-                (*(*info).ContextRecord).Rip = hook as u64;
-                log_file.get_mut().write(format!("RCX: {:#x}\n",(*(*info).ContextRecord).Rcx).as_bytes()).unwrap();
-                log_file.get_mut().write("We found the function!\n".as_bytes()).unwrap();
-                let mut old_prot: DWORD = 0;
-                VirtualProtect(orig as _,1,PAGE_EXECUTE_READWRITE,&mut old_prot);
-                   // panic!("VirtualProtect failed");
-
-                std::ptr::copy_nonoverlapping(&raw const og_byte,orig as _,1);
-                VirtualProtect(orig as _,1,old_prot,&mut old_prot);
-                return EXCEPTION_CONTINUE_EXECUTION;
-            }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    // retour inline detour; stays in place until disabled.
+    Detour,
+    // INT3 on the first byte, redirected from a vectored exception handler. It fires once: the original byte is put
+    // back on the hit so the replacement can call through to the target.
+    Breakpoint,
+}
+
+// Lets detours of different function types share one registry.
+pub trait DetourControl: Sync {
+    unsafe fn enable(&self) -> retour::Result<()>;
+    unsafe fn disable(&self) -> retour::Result<()>;
+    fn is_enabled(&self) -> bool;
+}
+impl<T: Function> DetourControl for StaticDetour<T> {
+    unsafe fn enable(&self) -> retour::Result<()> {
+        unsafe {StaticDetour::enable(self)}
+    }
+    unsafe fn disable(&self) -> retour::Result<()> {
+        unsafe {StaticDetour::disable(self)}
+    }
+    fn is_enabled(&self) -> bool {
+        StaticDetour::is_enabled(self)
+    }
+}
+
+enum Backend {
+    Detour(&'static dyn DetourControl),
+    Breakpoint { replacement: usize, original_byte: u8 },
+}
+struct Entry {
+    name: &'static str,
+    target: usize,
+    enabled: bool,
+    backend: Backend,
+}
+impl Entry {
+    fn kind(&self) -> HookKind {
+        match self.backend {
+            Backend::Detour(_) => HookKind::Detour,
+            Backend::Breakpoint { .. } => HookKind::Breakpoint,
         }
-        EXCEPTION_CONTINUE_SEARCH
-    }
-    pub fn new(addr: usize,hook_addr: usize)  {
-        unsafe {
-            let mut old_prot: DWORD = 0;
-            hook = hook_addr;
-            orig = addr;
-            VirtualProtect(addr as _,1,PAGE_EXECUTE_READWRITE, &mut old_prot);
-            let breakpnt = [0xCCu32];
-            std::ptr::copy_nonoverlapping(addr as _,&raw mut og_byte,1);
-            log_file.get_mut().write(format!("og_byte: {:#x}!\n",*(&raw const og_byte)).as_bytes()).unwrap();
-            std::ptr::copy_nonoverlapping(breakpnt.as_ptr(),addr as _,breakpnt.len());
-            VirtualProtect(addr as _,1,old_prot,&mut old_prot);
-            let handle = AddVectoredExceptionHandler(1, Some(Self::exception_handler)) as *mut std::ffi::c_void;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookState {
+    pub name: &'static str,
+    pub kind: HookKind,
+    pub target: usize,
+    pub enabled: bool,
+}
+impl fmt::Display for HookState {
+    fn fmt(&self,f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"{} ({:?} at {:#x}, {})",self.name,self.kind,self.target,if self.enabled { "enabled" } else { "disabled" })
+    }
+}
+
+static HOOKS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+// Handle from AddVectoredExceptionHandler, 0 while no breakpoint hook is registered.
+static VEH_HANDLE: Mutex<usize> = Mutex::new(0);
+
+// Writes one byte of code and returns the one it replaced.
+unsafe fn patch_byte(addr: usize,byte: u8) -> u8 {
+    unsafe {
+        let mut old_prot: DWORD = 0;
+        VirtualProtect(addr as _,1,PAGE_EXECUTE_READWRITE,&mut old_prot);
+        let previous = std::ptr::read_volatile(addr as *const u8);
+        std::ptr::write_volatile(addr as *mut u8,byte);
+        VirtualProtect(addr as _,1,old_prot,&mut old_prot);
+        previous
+    }
+}
+
+unsafe extern "system" fn breakpoint_handler(info: PEXCEPTION_POINTERS) -> i32 {
+    unsafe {
+        let record = &*(*info).ExceptionRecord;
+        if record.ExceptionCode != EXCEPTION_BREAKPOINT {
+            return EXCEPTION_CONTINUE_SEARCH;
         }
+        let addr = record.ExceptionAddress as usize;
+        // A panic can't unwind out of an exception handler, so a poisoned lock is used as is.
+        let mut hooks = HOOKS.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(entry) = hooks.iter_mut().find(|entry| entry.target == addr && entry.enabled) else {
+            return EXCEPTION_CONTINUE_SEARCH;
+        };
+        let Backend::Breakpoint { replacement, original_byte } = entry.backend else {
+            return EXCEPTION_CONTINUE_SEARCH;
+        };
+        patch_byte(addr,original_byte);
+        entry.enabled = false;
+        (*(*info).ContextRecord).Rip = replacement as u64;
+        EXCEPTION_CONTINUE_EXECUTION
     }
-}
\ No newline at end of file
+}
+
+fn insert(entry: Entry) {
+    let mut hooks = HOOKS.lock().unwrap();
+    if let Some(existing) = hooks.iter().position(|existing| existing.name == entry.name) {
+        log::warn!("Hook {} registered twice, replacing it",entry.name);
+        hooks.remove(existing);
+    }
+    hooks.push(entry);
+}
+
+// The detour must already be initialized; it is registered disabled.
+pub fn register_detour(name: &'static str,detour: &'static dyn DetourControl,target: usize) {
+    insert(Entry { name, target, enabled: detour.is_enabled(), backend: Backend::Detour(detour) });
+}
+
+// Registered disabled; the INT3 is only written by `enable`.
+pub fn register_breakpoint(name: &'static str,target: usize,replacement: usize) {
+    {
+        let mut handle = VEH_HANDLE.lock().unwrap();
+        if *handle == 0 {
+            *handle = unsafe {AddVectoredExceptionHandler(1,Some(breakpoint_handler))} as usize;
+        }
+    }
+    insert(Entry { name, target, enabled: false, backend: Backend::Breakpoint { replacement, original_byte: 0 } });
+}
+
+fn set_enabled(entry: &mut Entry,enabled: bool) -> Result<(),String> {
+    if entry.enabled == enabled {
+        return Ok(());
+    }
+    match &mut entry.backend {
+        Backend::Detour(detour) => {
+            let result = unsafe {if enabled { detour.enable() } else { detour.disable() }};
+            result.map_err(|err| err.to_string())?;
+        },
+        Backend::Breakpoint { original_byte, .. } => unsafe {
+            if enabled {
+                *original_byte = patch_byte(entry.target,0xCC);
+            } else {
+                patch_byte(entry.target,*original_byte);
+            }
+        },
+    }
+    entry.enabled = enabled;
+    Ok(())
+}
+fn with_entry<R>(name: &str,f: impl FnOnce(&mut Entry) -> Result<R,String>) -> Result<R,String> {
+    let mut hooks = HOOKS.lock().unwrap();
+    let entry = hooks.iter_mut().find(|entry| entry.name == name).ok_or_else(|| format!("no hook named {}",name))?;
+    f(entry)
+}
+
+pub fn enable(name: &str) -> Result<(),String> {
+    with_entry(name,|entry| set_enabled(entry,true))?;
+    log::info!("Enabled hook {}",name);
+    Ok(())
+}
+pub fn disable(name: &str) -> Result<(),String> {
+    with_entry(name,|entry| set_enabled(entry,false))?;
+    log::info!("Disabled hook {}",name);
+    Ok(())
+}
+pub fn is_enabled(name: &str) -> Option<bool> {
+    // A breakpoint that already fired has flipped itself off, so always read the live flag.
+    with_entry(name,|entry| Ok(match entry.backend {
+        Backend::Detour(detour) => detour.is_enabled(),
+        Backend::Breakpoint { .. } => entry.enabled,
+    })).ok()
+}
+pub fn states() -> Vec<HookState> {
+    HOOKS.lock().unwrap().iter().map(|entry| HookState {
+        name: entry.name,
+        kind: entry.kind(),
+        target: entry.target,
+        enabled: match entry.backend {
+            Backend::Detour(detour) => detour.is_enabled(),
+            Backend::Breakpoint { .. } => entry.enabled,
+        },
+    }).collect()
+}
+
+// Puts every patched function back and drops the exception handler, for unload.
+pub fn remove_all() {
+    let mut hooks = HOOKS.lock().unwrap();
+    for mut entry in hooks.drain(..) {
+        match set_enabled(&mut entry,false) {
+            Ok(()) => log::info!("Removed hook {}",entry.name),
+            Err(err) => log::warn!("Could not restore hook {}: {}",entry.name,err),
+        }
+    }
+    drop(hooks);
+    let mut handle = VEH_HANDLE.lock().unwrap();
+    if *handle != 0 {
+        unsafe {RemoveVectoredExceptionHandler(*handle as _)};
+        *handle = 0;
+    }
+}
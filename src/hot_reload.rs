@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::compute_shader_interop::ComputeShader;
+use crate::config::CONFIG;
+use crate::DEVICE;
+use crate::device::{has_capability, CAPABILITY_RAY_QUERY};
+use crate::render::{build_kernel, current_backend, uses_ray_query, EmbeddedKernel, EMBEDDED_KERNELS};
+use crate::shader_loader;
+
+// Dev mode: watches the override directory and rebuilds kernels whose file changed.
+// The watcher thread only queues paths; the rebuild happens on the render thread before the kernel's next dispatch.
+static WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+static PENDING: LazyLock<Mutex<HashSet<&'static str>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+fn embedded_for(dir: &Path,path: &Path) -> Option<&'static EmbeddedKernel> {
+    let relative = path.strip_prefix(dir).ok()?;
+    let relative = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+    EMBEDDED_KERNELS.iter().find(|kernel| kernel.path == relative)
+}
+
+pub fn start() {
+    let config = CONFIG.get_mut().shaders.clone();
+    if !config.hot_reload {
+        return;
+    }
+    let Some(dir) = config.dir.filter(|dir| dir.is_dir()) else {
+        log::warn!("Shader hot reload is on but the shader directory does not exist, not watching");
+        return;
+    };
+    let dir: PathBuf = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(err) => {
+            log::warn!("Could not resolve {}, not watching: {}",dir.display(),err);
+            return;
+        }
+    };
+    let watched = dir.clone();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                log::warn!("Shader watcher error: {}",err);
+                return;
+            }
+        };
+        if !matches!(event.kind,EventKind::Create(_) | EventKind::Modify(_)) {
+            return;
+        }
+        for path in &event.paths {
+            if let Some(kernel) = embedded_for(&watched,path) {
+                if PENDING.lock().unwrap().insert(kernel.path) {
+                    log::debug!("{}/{} changed on disk, rebuilding before its next dispatch",kernel.group,kernel.kernel);
+                }
+            }
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            log::error!("Could not start the shader watcher: {}",err);
+            return;
+        }
+    };
+    if let Err(err) = watcher.watch(&dir,RecursiveMode::Recursive) {
+        log::error!("Could not watch {}: {}",dir.display(),err);
+        return;
+    }
+    log::info!("Watching {} for shader changes",dir.display());
+    WATCHER.lock().unwrap().replace(watcher);
+}
+
+pub fn stop() {
+    if WATCHER.lock().unwrap().take().is_some() {
+        log::debug!("Stopped watching for shader changes");
+    }
+    PENDING.lock().unwrap().clear();
+}
+
+// Rebuilds the queued kernels of this shader's group. Must be called while DEVICE is not borrowed.
+// A kernel that fails to build keeps its last good version and is retried on its next change.
+pub(crate) fn apply_pending(shader: &mut ComputeShader) {
+    if !shader.embedded {
+        return;
+    }
+    let kernels: Vec<&'static EmbeddedKernel> = {
+        let mut pending = PENDING.lock().unwrap();
+        if pending.is_empty() {
+            return;
+        }
+        let kernels = EMBEDDED_KERNELS.iter().filter(|kernel| kernel.group == shader.group && pending.contains(kernel.path)).collect::<Vec<_>>();
+        for kernel in &kernels {
+            pending.remove(kernel.path);
+        }
+        kernels
+    };
+    if kernels.is_empty() {
+        return;
+    }
+    let backend = current_backend();
+    let binding = DEVICE.get_mut();
+    let Some((device,_)) = binding.as_ref() else {
+        return;
+    };
+    for kernel in kernels {
+        let Some(spirv) = shader_loader::load_override(kernel) else {
+            log::warn!("Could not load the changed {}/{}, keeping the current build",kernel.group,kernel.kernel);
+            continue;
+        };
+        if !has_capability(CAPABILITY_RAY_QUERY) && uses_ray_query(&spirv) {
+            log::error!("The changed {}/{} needs ray query, which this device lacks; keeping the current build",kernel.group,kernel.kernel);
+            continue;
+        }
+        match build_kernel(device,backend,kernel.kernel,&spirv) {
+            Ok(built) => shader.reload_kernel(kernel.kernel,built),
+            Err(err) => log::error!("Reloading {}/{} failed, keeping the current build: {}",kernel.group,kernel.kernel,err),
+        }
+    }
+}
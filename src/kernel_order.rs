@@ -0,0 +1,75 @@
+// Shared with build.rs, which includes this file by path; the lib only compiles it for its tests.
+//
+// Kernel indices are 0-based and must match what Unity's ComputeShader.FindKernel returns, since C# passes them
+// straight back to Dispatch and the setters: the n-th `#pragma kernel` of <group>.compute gets index n. Kernels the
+// group source doesn't declare (per-kernel .compute files, or bare .spv) come after the declared ones, sorted by name.
+
+// `#pragma kernel` names in declaration order. Anything after the name (e.g. `KERNEL_DEFINE=1`) is ignored.
+pub(crate) fn pragma_kernels(source: &str) -> Vec<String> {
+    source.lines()
+        .filter_map(|line| line.trim().strip_prefix("#pragma kernel"))
+        .filter(|rest| rest.starts_with(char::is_whitespace))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .map(String::from)
+        .collect()
+}
+
+// Index order for a group: `declared` as-is, then every other name in `others` sorted. A kernel in both keeps its
+// declared index.
+pub(crate) fn kernel_order(declared: Vec<String>,others: impl IntoIterator<Item = String>) -> Vec<String> {
+    let declared_count = declared.len();
+    let mut order = declared;
+    for kernel in others {
+        if !order.contains(&kernel) {
+            order.push(kernel);
+        }
+    }
+    order[declared_count..].sort();
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_pragmas_in_declaration_order() {
+        let source = "
+#include \"CommonData.cginc\"
+#pragma kernel Generate
+  #pragma kernel\tGeneratePanorama KERNEL_PANORAMA
+// #pragma kernel Disabled
+#pragma kernels NotAKernel
+#pragma multi_compile _ HardwareRT
+uint Frame;
+#pragma kernel CacheResolve
+[numthreads(64,1,1)]
+void CacheResolve(uint3 id : SV_DispatchThreadID) {}
+";
+        assert_eq!(pragma_kernels(source),names(&["Generate","GeneratePanorama","CacheResolve"]));
+        assert!(pragma_kernels("").is_empty());
+    }
+
+    #[test]
+    fn reads_the_bundled_group_sources() {
+        let read = |group: &str| pragma_kernels(&std::fs::read_to_string(format!("{}/src/shaders/main/{}.compute",env!("CARGO_MANIFEST_DIR"),group)).unwrap());
+        assert_eq!(read("RayGenKernels"),names(&["Generate","GeneratePanorama","CacheResolve","CacheCompact"]));
+        assert_eq!(read("ReSTIRGI"),names(&["ReSTIRGIKernel","ReSTIRGISpatial","ReSTIRGISpatial2"]));
+        let shading = read("RayTracingShader");
+        assert_eq!(shading[0],"kernel_shade");
+        assert_eq!(shading.iter().position(|kernel| kernel == "MVKernel"),Some(6));
+    }
+
+    #[test]
+    fn undeclared_kernels_follow_sorted() {
+        let order = kernel_order(names(&["Trace","Shade"]),names(&["Shade","Zeta","Alpha","Trace","Alpha"]));
+        assert_eq!(order,names(&["Trace","Shade","Alpha","Zeta"]));
+        // Groups without a .compute of their own are all name order.
+        let order = kernel_order(Vec::new(),names(&["kernel_trace","kernel_heightmap","kernel_shadow"]));
+        assert_eq!(order,names(&["kernel_heightmap","kernel_shadow","kernel_trace"]));
+    }
+}
@@ -0,0 +1,200 @@
+use spirv_reflect::types::ReflectDescriptorType;
+use wgpu_types::Limits;
+use crate::config::LimitsConfig;
+
+// Per-stage binding usage of one kernel, as reflected from its SPIR-V.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BindingCounts {
+    pub storage_buffers: u32,
+    pub uniform_buffers: u32,
+    pub sampled_textures: u32,
+    pub storage_textures: u32,
+    pub samplers: u32,
+    pub acceleration_structures: u32,
+    pub bindings_per_group: u32,
+}
+
+pub fn count_bindings(spirv: &[u32]) -> Result<BindingCounts,String> {
+    let module = spirv_reflect::ShaderModule::load_u32_data(spirv).map_err(str::to_string)?;
+    let mut counts = BindingCounts::default();
+    for binding in module.enumerate_descriptor_bindings(None).map_err(str::to_string)? {
+        counts.bindings_per_group = counts.bindings_per_group.max(binding.binding + 1);
+        let count = binding.count.max(1);
+        match binding.descriptor_type {
+            ReflectDescriptorType::StorageBuffer | ReflectDescriptorType::StorageBufferDynamic | ReflectDescriptorType::StorageTexelBuffer => counts.storage_buffers += count,
+            ReflectDescriptorType::UniformBuffer | ReflectDescriptorType::UniformBufferDynamic | ReflectDescriptorType::UniformTexelBuffer => counts.uniform_buffers += count,
+            ReflectDescriptorType::SampledImage => counts.sampled_textures += count,
+            ReflectDescriptorType::StorageImage => counts.storage_textures += count,
+            ReflectDescriptorType::Sampler => counts.samplers += count,
+            ReflectDescriptorType::AccelerationStructureKHR => counts.acceleration_structures += count,
+            _ => {},
+        }
+    }
+    Ok(counts)
+}
+
+// Kernels that use more of one limit than the adapter supports, with what they use.
+fn kernels_over(kernels: &[(String,BindingCounts)],count: fn(&BindingCounts) -> u32,supported: u32) -> Vec<(&str,u32)> {
+    kernels.iter().filter(|(_,counts)| count(counts) > supported).map(|(kernel,counts)| (kernel.as_str(),count(counts))).collect()
+}
+
+// Picks the value for one limit: the config override if set, otherwise the most any kernel uses (never below the
+// default), capped at what the adapter supports. Kernels that need more than the adapter has are reported by name.
+fn fit(name: &str,kernels: &[(String,BindingCounts)],count: fn(&BindingCounts) -> u32,default: u32,supported: u32,override_value: Option<u32>) -> u32 {
+    let required = kernels.iter().map(|(_,counts)| count(counts)).max().unwrap_or(0);
+    let wanted = override_value.unwrap_or(required.max(default));
+    for (kernel,used) in kernels_over(kernels,count,supported) {
+        log::error!("{} uses {} {}, the adapter supports {}",kernel,used,name,supported);
+    }
+    if wanted > supported {
+        log::warn!("Clamping {} from {} to the adapter's {}",name,wanted,supported);
+        return supported;
+    }
+    wanted
+}
+
+// wgpu's defaults, lowered to the adapter wherever it falls short (a GL or downlevel adapter usually does), so that
+// limits no kernel drives don't fail the device request.
+fn adapter_defaults(supported: &Limits) -> Limits {
+    let mut limits = Limits::defaults();
+    let mut lowered = Vec::new();
+    // The same limits Limits::check_limits compares; `max` ones go down to the adapter, `min` ones (alignments) up.
+    macro_rules! fit_to_adapter {
+        (max: $($max:ident),*; min: $($min:ident),*) => {
+            $(if limits.$max > supported.$max {
+                lowered.push(format!("{} {} -> {}",stringify!($max),limits.$max,supported.$max));
+                limits.$max = supported.$max;
+            })*
+            $(if limits.$min < supported.$min {
+                lowered.push(format!("{} {} -> {}",stringify!($min),limits.$min,supported.$min));
+                limits.$min = supported.$min;
+            })*
+        };
+    }
+    fit_to_adapter!(
+        max: max_texture_dimension_1d, max_texture_dimension_2d, max_texture_dimension_3d, max_texture_array_layers, max_bind_groups,
+            max_bindings_per_bind_group, max_dynamic_uniform_buffers_per_pipeline_layout, max_dynamic_storage_buffers_per_pipeline_layout,
+            max_sampled_textures_per_shader_stage, max_samplers_per_shader_stage, max_storage_buffers_per_shader_stage,
+            max_storage_textures_per_shader_stage, max_uniform_buffers_per_shader_stage, max_binding_array_elements_per_shader_stage,
+            max_uniform_buffer_binding_size, max_storage_buffer_binding_size, max_vertex_buffers, max_buffer_size, max_vertex_attributes,
+            max_vertex_buffer_array_stride, max_inter_stage_shader_components, max_color_attachments, max_color_attachment_bytes_per_sample,
+            max_compute_workgroup_storage_size, max_compute_invocations_per_workgroup, max_compute_workgroup_size_x,
+            max_compute_workgroup_size_y, max_compute_workgroup_size_z, max_compute_workgroups_per_dimension, max_subgroup_size,
+            max_push_constant_size, max_non_sampler_bindings, max_blas_primitive_count, max_blas_geometry_count, max_tlas_instance_count,
+            max_acceleration_structures_per_shader_stage;
+        min: min_uniform_buffer_offset_alignment, min_storage_buffer_offset_alignment, min_subgroup_size
+    );
+    if !lowered.is_empty() {
+        log::warn!("Adapter is below wgpu's default limits, lowering {}",lowered.join(", "));
+    }
+    limits
+}
+
+pub fn derive_limits(kernels: &[(String,BindingCounts)],supported: &Limits,overrides: &LimitsConfig) -> Limits {
+    let defaults = adapter_defaults(supported);
+    let limits = Limits {
+        max_storage_buffers_per_shader_stage: fit("storage buffers",kernels,|c| c.storage_buffers,defaults.max_storage_buffers_per_shader_stage,supported.max_storage_buffers_per_shader_stage,overrides.max_storage_buffers_per_shader_stage),
+        max_uniform_buffers_per_shader_stage: fit("uniform buffers",kernels,|c| c.uniform_buffers,defaults.max_uniform_buffers_per_shader_stage,supported.max_uniform_buffers_per_shader_stage,None),
+        max_sampled_textures_per_shader_stage: fit("sampled textures",kernels,|c| c.sampled_textures,defaults.max_sampled_textures_per_shader_stage,supported.max_sampled_textures_per_shader_stage,None),
+        max_storage_textures_per_shader_stage: fit("storage textures",kernels,|c| c.storage_textures,defaults.max_storage_textures_per_shader_stage,supported.max_storage_textures_per_shader_stage,overrides.max_storage_textures_per_shader_stage),
+        max_samplers_per_shader_stage: fit("samplers",kernels,|c| c.samplers,defaults.max_samplers_per_shader_stage,supported.max_samplers_per_shader_stage,None),
+        max_acceleration_structures_per_shader_stage: fit("acceleration structures",kernels,|c| c.acceleration_structures,defaults.max_acceleration_structures_per_shader_stage,supported.max_acceleration_structures_per_shader_stage,overrides.max_acceleration_structures_per_shader_stage),
+        max_bindings_per_bind_group: fit("bindings per group",kernels,|c| c.bindings_per_group,defaults.max_bindings_per_bind_group,supported.max_bindings_per_bind_group,None),
+        ..defaults
+    };
+    log::debug!("Derived limits from {} kernels: {:?}",kernels.len(),limits);
+    limits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kernel(name: &str,storage_buffers: u32,acceleration_structures: u32) -> (String,BindingCounts) {
+        (name.to_string(),BindingCounts { storage_buffers, acceleration_structures, bindings_per_group: storage_buffers + acceleration_structures, ..BindingCounts::default() })
+    }
+    // An empty compute kernel with a storage buffer at binding 2 and an RWTexture2D<float4> at binding 5:
+    //   OpCapability Shader; OpMemoryModel Logical GLSL450; OpEntryPoint GLCompute %main "main"; LocalSize 1 1 1
+    //   %buf: Uniform BufferBlock { uint } (set 0, binding 2); %rt: UniformConstant Image 2D sampled=2 Rgba32f (set 0, binding 5)
+    const TWO_BINDINGS: &[u32] = &[
+        0x0723_0203, 0x0001_0300, 0, 13, 0,
+        0x0002_0011, 1,
+        0x0003_000E, 0, 1,
+        0x0005_000F, 5, 1, 0x6E69_616D, 0,
+        0x0006_0010, 1, 17, 1, 1, 1,
+        0x0004_0047, 7, 34, 0,
+        0x0004_0047, 7, 33, 2,
+        0x0003_0047, 5, 3,
+        0x0005_0048, 5, 0, 35, 0,
+        0x0004_0047, 11, 34, 0,
+        0x0004_0047, 11, 33, 5,
+        0x0002_0013, 2,
+        0x0003_0021, 3, 2,
+        0x0004_0015, 4, 32, 0,
+        0x0003_001E, 5, 4,
+        0x0004_0020, 6, 2, 5,
+        0x0004_003B, 6, 7, 2,
+        0x0003_0016, 8, 32,
+        0x0009_0019, 9, 8, 1, 0, 0, 0, 2, 1,
+        0x0004_0020, 10, 0, 9,
+        0x0004_003B, 10, 11, 0,
+        0x0005_0036, 2, 1, 0, 3,
+        0x0002_00F8, 12,
+        0x0001_00FD,
+        0x0001_0038,
+    ];
+
+    #[test]
+    fn counts_reflected_bindings() {
+        let counts = count_bindings(TWO_BINDINGS).unwrap();
+        assert_eq!(counts,BindingCounts { storage_buffers: 1, storage_textures: 1, bindings_per_group: 6, ..BindingCounts::default() });
+        assert!(count_bindings(&[0x0723_0203, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn fits_to_kernels_defaults_and_overrides() {
+        let kernels = [kernel("a",12,1), kernel("b",30,2)];
+        // The most any kernel uses, but never below the default.
+        assert_eq!(fit("storage buffers",&kernels,|c| c.storage_buffers,8,64,None),30);
+        assert_eq!(fit("storage buffers",&kernels,|c| c.storage_buffers,40,64,None),40);
+        assert_eq!(fit("acceleration structures",&[],|c| c.acceleration_structures,0,16,None),0);
+        // An override replaces the derived value, in either direction.
+        assert_eq!(fit("storage buffers",&kernels,|c| c.storage_buffers,8,64,Some(50)),50);
+        assert_eq!(fit("storage buffers",&kernels,|c| c.storage_buffers,8,64,Some(20)),20);
+    }
+
+    #[test]
+    fn clamps_to_the_adapter() {
+        let kernels = [kernel("a",12,1), kernel("b",30,2)];
+        assert_eq!(fit("storage buffers",&kernels,|c| c.storage_buffers,8,16,None),16);
+        assert_eq!(fit("storage buffers",&kernels,|c| c.storage_buffers,8,16,Some(50)),16);
+        assert_eq!(fit("storage buffers",&[],|c| c.storage_buffers,8,4,None),4);
+    }
+
+    #[test]
+    fn reports_kernels_over_the_adapter_limit() {
+        let kernels = [kernel("a",12,1), kernel("b",30,2), kernel("c",17,0)];
+        assert_eq!(kernels_over(&kernels,|c| c.storage_buffers,16),vec![("b",30), ("c",17)]);
+        assert_eq!(kernels_over(&kernels,|c| c.acceleration_structures,2),vec![]);
+    }
+
+    #[test]
+    fn derived_limits_stay_within_the_adapter() {
+        let kernels = [kernel("a",12,1), kernel("b",30,2)];
+        for supported in [Limits::defaults(), Limits::downlevel_defaults(), Limits::downlevel_webgl2_defaults()] {
+            let limits = derive_limits(&kernels,&supported,&LimitsConfig::default());
+            assert!(limits.check_limits(&supported),"{:?}",limits);
+            assert_eq!(limits.max_storage_buffers_per_shader_stage,supported.max_storage_buffers_per_shader_stage.min(30));
+        }
+    }
+
+    #[test]
+    fn derived_limits_apply_config_overrides() {
+        let kernels = [kernel("a",12,1), kernel("b",30,2)];
+        let supported = Limits { max_storage_buffers_per_shader_stage: 64, max_acceleration_structures_per_shader_stage: 16, ..Limits::defaults() };
+        let overrides = LimitsConfig { max_storage_buffers_per_shader_stage: Some(50), max_acceleration_structures_per_shader_stage: Some(100), ..LimitsConfig::default() };
+        let limits = derive_limits(&kernels,&supported,&overrides);
+        assert_eq!(limits.max_storage_buffers_per_shader_stage,50);
+        assert_eq!(limits.max_acceleration_structures_per_shader_stage,16);
+    }
+}
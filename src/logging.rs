@@ -0,0 +1,320 @@
+use std::collections::VecDeque;
+use std::ffi::{c_char, CStr, CString};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use crate::config::CONFIG;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    // (target prefix, level), longest prefix wins.
+    directives: Vec<(String,LevelFilter)>,
+}
+impl Filter {
+    // Same shape as RUST_LOG: `info,wgpu_core=warn,TrueTraceRust::render=debug`.
+    pub fn parse(spec: &str) -> Result<Filter,String> {
+        let mut filter = Filter { default: LevelFilter::Info, directives: Vec::new() };
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let parse_level = |level: &str| level.trim().parse::<LevelFilter>().map_err(|_| format!("unknown log level {:?} in {:?}",level,spec));
+            match part.split_once('=') {
+                Some((target, level)) => filter.directives.push((target.trim().to_string(),parse_level(level)?)),
+                None => filter.default = parse_level(part)?,
+            }
+        }
+        filter.directives.sort_by_key(|(target,_)| std::cmp::Reverse(target.len()));
+        Ok(filter)
+    }
+    pub fn level_for(&self,target: &str) -> LevelFilter {
+        self.directives.iter()
+            .find(|(prefix,_)| target.starts_with(prefix.as_str()))
+            .map_or(self.default,|(_,level)| *level)
+    }
+    fn max_level(&self) -> LevelFilter {
+        self.directives.iter().map(|(_,level)| *level).chain(std::iter::once(self.default)).max().unwrap_or(LevelFilter::Info)
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    written: u64,
+    max_size: u64,
+    max_files: u32,
+}
+impl RotatingFile {
+    fn open(path: &Path,max_size: u64,max_files: u32) -> RotatingFile {
+        let mut rotating = RotatingFile { path: path.to_path_buf(), file: None, written: 0, max_size, max_files };
+        rotating.rotate();
+        rotating
+    }
+    fn numbered(&self,index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}",index));
+        PathBuf::from(name)
+    }
+    // truetrace_nativelog.txt -> .1 -> .2 ... and the oldest one is dropped.
+    fn rotate(&mut self) {
+        self.file = None;
+        if self.max_files > 0 {
+            let _ = std::fs::remove_file(self.numbered(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let _ = std::fs::rename(self.numbered(index),self.numbered(index + 1));
+            }
+            let _ = std::fs::rename(&self.path,self.numbered(1));
+        }
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path).ok();
+        self.written = 0;
+    }
+    fn write_line(&mut self,line: &str) {
+        if self.max_size > 0 && self.written + line.len() as u64 > self.max_size {
+            self.rotate();
+        }
+        if let Some(file) = self.file.as_mut() {
+            if file.write_all(line.as_bytes()).is_ok() {
+                self.written += line.len() as u64;
+            }
+            let _ = file.flush();
+        }
+    }
+}
+
+// Matches Unity's LogType order for the values we forward: 0 = Log, 1 = Warning, 2 = Error.
+pub type LogCallback = extern "C" fn(level: i32,message: *const c_char);
+const MAX_PENDING: usize = 1024;
+
+struct Forwarder {
+    callback: Option<LogCallback>,
+    pending: VecDeque<(i32,String)>,
+}
+static FORWARDER: Mutex<Forwarder> = Mutex::new(Forwarder { callback: None, pending: VecDeque::new() });
+
+fn unity_level(level: Level) -> i32 {
+    match level {
+        Level::Error => 2,
+        Level::Warn => 1,
+        Level::Info | Level::Debug | Level::Trace => 0,
+    }
+}
+fn deliver(callback: LogCallback,level: i32,message: &str) {
+    let message = CString::new(message.replace('\0',"")).unwrap_or_default();
+    callback(level,message.as_ptr());
+}
+// The callback runs outside the lock so a delegate that logs back into us can't deadlock.
+fn forward(level: i32,message: String) {
+    let callback = {
+        let mut forwarder = FORWARDER.lock().unwrap();
+        match forwarder.callback {
+            Some(callback) => callback,
+            None => {
+                if forwarder.pending.len() == MAX_PENDING {
+                    forwarder.pending.pop_front();
+                }
+                forwarder.pending.push_back((level,message));
+                return;
+            }
+        }
+    };
+    deliver(callback,level,&message);
+}
+pub fn set_callback(callback: Option<LogCallback>) {
+    let pending = {
+        let mut forwarder = FORWARDER.lock().unwrap();
+        forwarder.callback = callback;
+        match callback {
+            Some(_) => std::mem::take(&mut forwarder.pending),
+            None => VecDeque::new(),
+        }
+    };
+    if let Some(callback) = callback {
+        for (level,message) in pending {
+            deliver(callback,level,&message);
+        }
+    }
+}
+
+struct NativeLogger {
+    filter: Mutex<Filter>,
+    file: Mutex<RotatingFile>,
+}
+fn format_line(level: Level,target: &str,message: &std::fmt::Arguments) -> String {
+    format!("{} {:<5} [{}] {}\n",chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),level,target,message)
+}
+impl Log for NativeLogger {
+    fn enabled(&self,metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.lock().unwrap().level_for(metadata.target())
+    }
+    fn log(&self,record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_line(record.level(),record.target(),record.args());
+        self.file.lock().unwrap().write_line(&line);
+        forward(unity_level(record.level()),format!("[TrueTrace {}] {}",record.target(),record.args()));
+    }
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap().file.as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+static LOGGER: OnceLock<NativeLogger> = OnceLock::new();
+
+pub fn init() {
+    let mut installed = false;
+    // Reported once the logger is up, there's nowhere to send it before that.
+    let mut filter_error = None;
+    let logger = LOGGER.get_or_init(|| {
+        installed = true;
+        let config = CONFIG.get_mut().log.clone();
+        let filter = Filter::parse(&config.filter).unwrap_or_else(|err| {
+            filter_error = Some(err);
+            Filter::parse("info").unwrap()
+        });
+        NativeLogger {
+            filter: Mutex::new(filter),
+            file: Mutex::new(RotatingFile::open(&config.native_log,config.max_size,config.max_files)),
+        }
+    });
+    if installed && log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.filter.lock().unwrap().max_level());
+        if let Some(err) = filter_error {
+            log::warn!("{}, using `info`",err);
+        }
+        if let Some(err) = crate::config::take_load_error() {
+            log::error!("TrueTrace config error, falling back to defaults: {}",err);
+        }
+    }
+}
+
+// For the panic hook: never blocks, and only writes the file. Unity isn't called, since its callback may be what
+// panicked, and if the panicking thread holds the file lock the line is dropped.
+pub fn try_log_error(target: &str,message: &str) {
+    let Some(logger) = LOGGER.get() else {
+        return;
+    };
+    if let Ok(mut file) = logger.file.try_lock() {
+        file.write_line(&format_line(Level::Error,target,&format_args!("{}",message)));
+    }
+}
+
+pub fn set_filter(spec: &str) -> Result<(),String> {
+    let filter = Filter::parse(spec)?;
+    let logger = LOGGER.get().ok_or("logging is not initialised")?;
+    log::set_max_level(filter.max_level());
+    *logger.filter.lock().unwrap() = filter;
+    log::info!("Log filter set to {:?}",spec);
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_SetLogFilter(filter: *const c_char) -> bool {
+    if filter.is_null() {
+        return false;
+    }
+    let spec = unsafe {CStr::from_ptr(filter)}.to_string_lossy();
+    match set_filter(&spec) {
+        Ok(()) => true,
+        Err(err) => {
+            log::warn!("Rejected log filter: {}",err);
+            false
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_SetLogCallback(callback: Option<LogCallback>) {
+    set_callback(callback);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory per test under the system temp dir.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("truetrace_logging_{}_{}",name,std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parses_default_and_directives() {
+        let filter = Filter::parse("warn, wgpu_core=error ,TrueTraceRust::render=debug").unwrap();
+        assert_eq!(filter.level_for("TrueTraceRust"),LevelFilter::Warn);
+        assert_eq!(filter.level_for("wgpu_core::device"),LevelFilter::Error);
+        assert_eq!(filter.level_for("TrueTraceRust::render"),LevelFilter::Debug);
+        assert_eq!(filter.level_for("TrueTraceRust::render::kernels"),LevelFilter::Debug);
+        assert_eq!(filter.max_level(),LevelFilter::Debug);
+        // Empty parts are skipped and the default stays `info`.
+        let filter = Filter::parse(",,").unwrap();
+        assert_eq!(filter.level_for("anything"),LevelFilter::Info);
+        assert_eq!(filter.max_level(),LevelFilter::Info);
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        // Order in the spec doesn't matter.
+        for spec in ["wgpu=warn,wgpu_hal=trace,wgpu_hal::dx12=off","wgpu_hal::dx12=off,wgpu_hal=trace,wgpu=warn"] {
+            let filter = Filter::parse(spec).unwrap();
+            assert_eq!(filter.level_for("wgpu_core"),LevelFilter::Warn);
+            assert_eq!(filter.level_for("wgpu_hal::vulkan"),LevelFilter::Trace);
+            assert_eq!(filter.level_for("wgpu_hal::dx12::device"),LevelFilter::Off);
+            assert_eq!(filter.level_for("naga"),LevelFilter::Info);
+        }
+        // The last bare level is the default.
+        assert_eq!(Filter::parse("debug,error").unwrap().level_for("naga"),LevelFilter::Error);
+    }
+
+    #[test]
+    fn rejects_unknown_levels() {
+        let err = Filter::parse("info,wgpu=loud").unwrap_err();
+        assert!(err.contains("\"loud\""),"{}",err);
+        assert!(Filter::parse("verbose").is_err());
+        assert!(Filter::parse("wgpu=").is_err());
+        assert_eq!(Filter::parse("WARN").unwrap().level_for("naga"),LevelFilter::Warn);
+    }
+
+    #[test]
+    fn rotates_at_the_size_limit() {
+        let dir = scratch("rotate");
+        let path = dir.join("native.txt");
+        std::fs::write(&path,"previous run\n").unwrap();
+        let mut file = RotatingFile::open(&path,10,2);
+        // Opening keeps the previous run's log as .1.
+        assert_eq!(std::fs::read_to_string(file.numbered(1)).unwrap(),"previous run\n");
+        file.write_line("aaaa\n");
+        file.write_line("bbbb\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),"aaaa\nbbbb\n");
+        file.write_line("cccc\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),"cccc\n");
+        assert_eq!(std::fs::read_to_string(file.numbered(1)).unwrap(),"aaaa\nbbbb\n");
+        assert_eq!(std::fs::read_to_string(file.numbered(2)).unwrap(),"previous run\n");
+        // Only max_files old logs are kept.
+        file.write_line("dddddddd\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),"dddddddd\n");
+        assert_eq!(std::fs::read_to_string(file.numbered(1)).unwrap(),"cccc\n");
+        assert_eq!(std::fs::read_to_string(file.numbered(2)).unwrap(),"aaaa\nbbbb\n");
+        assert!(!file.numbered(3).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn zero_limits_never_rotate() {
+        let dir = scratch("unlimited");
+        let path = dir.join("native.txt");
+        std::fs::write(&path,"previous run\n").unwrap();
+        let mut file = RotatingFile::open(&path,0,0);
+        assert!(!file.numbered(1).exists());
+        for _ in 0..100 {
+            file.write_line("line\n");
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap().len(),500);
+        assert!(!file.numbered(1).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::sync::{LazyLock, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::config::{MemoryConfig, CONFIG};
+
+pub const MEMORY_BVH: u32 = 0;
+pub const MEMORY_TRIANGLES: u32 = 1;
+pub const MEMORY_ATLASES: u32 = 2;
+pub const MEMORY_RESERVOIRS: u32 = 3;
+pub const MEMORY_RAYS: u32 = 4;
+pub const MEMORY_TEXTURES: u32 = 5;
+pub const MEMORY_GLOBALS: u32 = 6;
+pub const MEMORY_OTHER: u32 = 7;
+pub const MEMORY_CATEGORY_COUNT: usize = 8;
+
+pub const MEMORY_LEVEL_NORMAL: u32 = 0;
+pub const MEMORY_LEVEL_WARNING: u32 = 1;
+pub const MEMORY_LEVEL_CRITICAL: u32 = 2;
+
+// `categories` is indexed by the MEMORY_* category constants. `budget` and `usage` are the adapter's local
+// (VRAM) segment for the whole process, Unity included; both are 0 where the backend can't report them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryStats {
+    pub categories: [u64; 8],
+    pub tracked_total: u64,
+    pub budget: u64,
+    pub usage: u64,
+    pub level: u32,
+}
+const _: () = assert!(MEMORY_CATEGORY_COUNT == 8);
+
+// Called whenever `level` changes, in either direction. Can run on any thread.
+pub type MemoryCallback = extern "C" fn(level: u32,stats: *const MemoryStats);
+
+struct Allocation {
+    category: u32,
+    size: u64,
+    refs: u32,
+}
+struct Tracker {
+    // Imported resources are keyed by their native pointer so the same Unity buffer bound to several kernels
+    // counts once; resources we create get a key from CREATED_KEYS.
+    allocations: HashMap<u64,Allocation>,
+    totals: [u64; MEMORY_CATEGORY_COUNT],
+    level: u32,
+    last_check: Option<Instant>,
+    callback: Option<MemoryCallback>,
+}
+static TRACKER: LazyLock<Mutex<Tracker>> = LazyLock::new(|| Mutex::new(Tracker {
+    allocations: HashMap::new(),
+    totals: [0; MEMORY_CATEGORY_COUNT],
+    level: MEMORY_LEVEL_NORMAL,
+    last_check: None,
+    callback: None,
+}));
+static CREATED_KEYS: AtomicU64 = AtomicU64::new(1 << 63);
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// Maps a shader binding name to a category, going by TrueTrace's naming.
+pub fn categorize(name: &str,is_texture: bool) -> u32 {
+    let has = |parts: &[&str]| parts.iter().any(|part| name.contains(part));
+    if name == "$Globals" {
+        MEMORY_GLOBALS
+    } else if has(&["Reservoir", "NEEPos", "WorldPos", "CacheBuffer", "VoxelData"]) {
+        MEMORY_RESERVOIRS
+    } else if has(&["Atlas", "Bindless", "Heightmap", "TerrainAlphaMap"]) {
+        MEMORY_ATLASES
+    } else if has(&["Node", "BVH", "bvh", "Agg", "Boxs", "Indices", "SGTree", "ReverseStack", "AccelerationStructure"]) {
+        MEMORY_BVH
+    } else if has(&["Tri", "bufVertices", "bufIndexes", "Mesh"]) {
+        MEMORY_TRIANGLES
+    } else if has(&["Rays"]) {
+        MEMORY_RAYS
+    } else if is_texture {
+        MEMORY_TEXTURES
+    } else {
+        MEMORY_OTHER
+    }
+}
+
+// Keeps an allocation counted for as long as it lives.
+#[derive(Debug)]
+pub struct Tracked {
+    key: u64,
+}
+// Another reference to the same allocation, e.g. an owned resource bound to a kernel; still counted once.
+impl Clone for Tracked {
+    fn clone(&self) -> Tracked {
+        if let Some(allocation) = TRACKER.lock().unwrap().allocations.get_mut(&self.key) {
+            allocation.refs += 1;
+        }
+        Tracked { key: self.key }
+    }
+}
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        release(self.key);
+    }
+}
+
+fn track(key: u64,size: u64,category: u32) -> Tracked {
+    {
+        let mut tracker = TRACKER.lock().unwrap();
+        let allocation = tracker.allocations.entry(key).or_insert(Allocation { category, size, refs: 0 });
+        allocation.refs += 1;
+        if allocation.refs == 1 {
+            tracker.totals[category as usize] += size;
+        }
+    }
+    check_budget(false);
+    Tracked { key }
+}
+pub fn track_native(raw: *mut c_void,size: u64,category: u32) -> Tracked {
+    track(raw as u64,size,category)
+}
+pub fn track_created(size: u64,category: u32) -> Tracked {
+    track(CREATED_KEYS.fetch_add(1,Ordering::Relaxed),size,category)
+}
+fn release(key: u64) {
+    let mut tracker = TRACKER.lock().unwrap();
+    let Some(allocation) = tracker.allocations.get_mut(&key) else {
+        return;
+    };
+    allocation.refs -= 1;
+    if allocation.refs == 0 {
+        let Allocation { category, size, .. } = tracker.allocations.remove(&key).unwrap();
+        tracker.totals[category as usize] -= size;
+    }
+}
+
+fn query_budget() -> Option<(u64,u64)> {
+    #[cfg(windows)]
+    return crate::ADAPTER.get_mut().as_ref().and_then(crate::shim::adapter_memory_info);
+    #[cfg(not(windows))]
+    None
+}
+fn level_for(usage: u64,budget: u64,config: &MemoryConfig) -> u32 {
+    if budget == 0 {
+        return MEMORY_LEVEL_NORMAL;
+    }
+    let percent = usage.saturating_mul(100) / budget;
+    if percent >= config.critical_percent as u64 {
+        MEMORY_LEVEL_CRITICAL
+    } else if percent >= config.warn_percent as u64 {
+        MEMORY_LEVEL_WARNING
+    } else {
+        MEMORY_LEVEL_NORMAL
+    }
+}
+
+pub fn stats() -> MemoryStats {
+    let (budget,usage) = query_budget().unwrap_or((0,0));
+    let config = CONFIG.get_mut().memory;
+    let tracker = TRACKER.lock().unwrap();
+    MemoryStats {
+        categories: tracker.totals,
+        tracked_total: tracker.totals.iter().sum(),
+        budget,
+        usage,
+        level: level_for(usage,budget,&config),
+    }
+}
+
+// Throttled unless forced, since it goes to the driver for the budget.
+pub fn check_budget(force: bool) {
+    {
+        let mut tracker = TRACKER.lock().unwrap();
+        if !force && tracker.last_check.is_some_and(|last| last.elapsed() < CHECK_INTERVAL) {
+            return;
+        }
+        tracker.last_check = Some(Instant::now());
+    }
+    let stats = stats();
+    let callback = {
+        let mut tracker = TRACKER.lock().unwrap();
+        if tracker.level == stats.level {
+            return;
+        }
+        tracker.level = stats.level;
+        tracker.callback
+    };
+    let mib = |bytes: u64| bytes / (1024 * 1024);
+    match stats.level {
+        MEMORY_LEVEL_CRITICAL => log::error!("GPU memory critical: {} of {} MiB in use, {} MiB tracked by TrueTrace",mib(stats.usage),mib(stats.budget),mib(stats.tracked_total)),
+        MEMORY_LEVEL_WARNING => log::warn!("GPU memory high: {} of {} MiB in use, {} MiB tracked by TrueTrace",mib(stats.usage),mib(stats.budget),mib(stats.tracked_total)),
+        _ => log::info!("GPU memory back under budget: {} of {} MiB in use",mib(stats.usage),mib(stats.budget)),
+    }
+    if let Some(callback) = callback {
+        callback(stats.level,&stats);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_GetMemoryStats(stats_out: *mut MemoryStats) -> bool {
+    if stats_out.is_null() {
+        return false;
+    }
+    unsafe {stats_out.write(stats())};
+    true
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_SetMemoryCallback(callback: Option<MemoryCallback>) {
+    TRACKER.lock().unwrap().callback = callback;
+    check_budget(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refs(key: u64) -> Option<u32> {
+        TRACKER.lock().unwrap().allocations.get(&key).map(|allocation| allocation.refs)
+    }
+
+    #[test]
+    fn categorizes_by_binding_name() {
+        assert_eq!(categorize("$Globals",false),MEMORY_GLOBALS);
+        assert_eq!(categorize("GlobalsBuffer",false),MEMORY_OTHER);
+        assert_eq!(categorize("CurrentReservoirGI",false),MEMORY_RESERVOIRS);
+        assert_eq!(categorize("NEEPosA",true),MEMORY_RESERVOIRS);
+        assert_eq!(categorize("_TextureAtlas",true),MEMORY_ATLASES);
+        assert_eq!(categorize("Heightmap",true),MEMORY_ATLASES);
+        assert_eq!(categorize("cwbvh_Nodes",false),MEMORY_BVH);
+        assert_eq!(categorize("AggTris",false),MEMORY_BVH);
+        assert_eq!(categorize("myAccelerationStructure",false),MEMORY_BVH);
+        assert_eq!(categorize("cwbvh_TrianglesB",false),MEMORY_BVH);
+        assert_eq!(categorize("TriangleBuffer",false),MEMORY_TRIANGLES);
+        assert_eq!(categorize("bufVertices",false),MEMORY_TRIANGLES);
+        assert_eq!(categorize("GlobalRays",false),MEMORY_RAYS);
+        assert_eq!(categorize("ScreenSpaceInfo",true),MEMORY_TEXTURES);
+        assert_eq!(categorize("ScreenSpaceInfo",false),MEMORY_OTHER);
+    }
+
+    #[test]
+    fn levels_follow_the_configured_percentages() {
+        let config = MemoryConfig { warn_percent: 80, critical_percent: 95, wgpu_creation_threshold: None };
+        // No budget from the backend never raises the level.
+        assert_eq!(level_for(u64::MAX,0,&config),MEMORY_LEVEL_NORMAL);
+        assert_eq!(level_for(0,1000,&config),MEMORY_LEVEL_NORMAL);
+        assert_eq!(level_for(799,1000,&config),MEMORY_LEVEL_NORMAL);
+        assert_eq!(level_for(800,1000,&config),MEMORY_LEVEL_WARNING);
+        assert_eq!(level_for(949,1000,&config),MEMORY_LEVEL_WARNING);
+        assert_eq!(level_for(950,1000,&config),MEMORY_LEVEL_CRITICAL);
+        assert_eq!(level_for(2000,1000,&config),MEMORY_LEVEL_CRITICAL);
+    }
+
+    #[test]
+    fn counts_shared_allocations_once() {
+        // A pointer no other test imports, since the tracker is global.
+        let raw = 0x7e57_0000usize as *mut c_void;
+        let first = track_native(raw,4096,MEMORY_RAYS);
+        let second = track_native(raw,4096,MEMORY_RAYS);
+        assert_eq!(refs(raw as u64),Some(2));
+        let third = first.clone();
+        assert_eq!(refs(raw as u64),Some(3));
+        drop(first);
+        drop(second);
+        assert_eq!(refs(raw as u64),Some(1));
+        drop(third);
+        assert_eq!(refs(raw as u64),None);
+    }
+
+    #[test]
+    fn created_allocations_get_their_own_keys() {
+        let first = track_created(256,MEMORY_GLOBALS);
+        let second = track_created(256,MEMORY_GLOBALS);
+        assert_ne!(first.key,second.key);
+        assert_eq!(refs(first.key),Some(1));
+        let key = first.key;
+        drop(first);
+        assert_eq!(refs(key),None);
+        assert_eq!(refs(second.key),Some(1));
+    }
+}
@@ -1,281 +1,506 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs::File;
 use std::hint::unreachable_unchecked;
-use std::io::Write;
-use std::mem::{forget, MaybeUninit};
+use std::mem::MaybeUninit;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU32, Ordering};
 use lazy_mut::LazyMut;
 use lazy_static::lazy::Lazy;
+use rayon::prelude::*;
 use spirv_cross2::compile::CompilableTarget;
 use spirv_cross2::compile::hlsl::HlslShaderModel;
-use spirv_reflect::types::{ReflectDecorationFlags, ReflectDescriptorType, ReflectImageFormat, ReflectTypeFlags};
-use wgpu::{include_spirv, include_spirv_raw, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BufferBindingType, Label, SamplerBindingType, ShaderModule, ShaderModuleDescriptor, ShaderModuleDescriptorPassthrough, ShaderSource, TextureSampleType};
+use spirv_reflect::types::{ReflectDecorationFlags, ReflectDescriptorBinding, ReflectDescriptorType, ReflectDimension, ReflectImageFormat, ReflectImageTraits, ReflectTypeFlags};
+use wgpu::{include_spirv, Backend, CompilationMessageType, include_spirv_raw, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BufferBindingType, Label, SamplerBindingType, ShaderModule, ShaderModuleDescriptor, ShaderModuleDescriptorPassthrough, ShaderSource, TextureSampleType};
 use wgpu::custom::{AsAny, DispatchShaderModule};
 use wgpu::naga::MathFunction::Reflect;
 use wgpu::wgt::CreateShaderModuleDescriptorPassthrough;
 use wgpu_hal::{Device, DynDevice, DynShaderModule, ShaderInput};
-use winapi::um::winuser::DefDlgProcA;
-use crate::{DEVICE, LOG_FILE};
+use crate::{ADAPTER_INFO, DEVICE};
+use crate::{crash, shader_cache, shader_loader};
+use crate::config::CONFIG;
+use crate::limits::{count_bindings, BindingCounts};
+use crate::device::{add_capability, has_capability, CAPABILITY_PASSTHROUGH_SHADERS, CAPABILITY_RAY_QUERY, CAPABILITY_SOFTWARE_BVH};
 use crate::shaders;
 use crate::shaders::{KernelStruct, Kernels};
 
 pub(crate) static KERNELS: LazyMut<Option<&'static mut Kernels>> = LazyMut::new(|| None);
 #[unsafe(no_mangle)]
 pub extern "C" fn init() {
-    std::panic::set_hook(Box::new(|panic_info| {
-        if let Ok(mut file) =  File::create("panic_log.txt") {
-            let _ = writeln!(file, "Panic occurred: {:?}", panic_info.payload_as_str().unwrap());
-            let location = panic_info.location().unwrap();
-            let _ = writeln!(file,"Occurred in file {} at line {}",location.file(),location.line());
+    crate::logging::init();
+    crash::install_hook();
+    build_kernels(shader_loader::load());
+    crate::hot_reload::start();
+}
+// Drops the current registry so its modules and layouts go before the device they were made on.
+pub(crate) fn release_kernels() {
+    if let Some(kernels) = KERNELS.get_mut().take() {
+        drop(unsafe {Box::from_raw(kernels as *mut Kernels)});
+    }
+}
+pub(crate) fn rebuild_kernels() {
+    release_kernels();
+    build_kernels(shader_loader::load());
+}
+pub(crate) struct EmbeddedKernel {
+    pub group: &'static str,
+    pub kernel: &'static str,
+    pub index: u32,
+    // Relative to src/shaders, and to the override directory on disk.
+    pub path: &'static str,
+    pub spirv: &'static [u8],
+}
+macro_rules! embedded {
+    ($group:literal,$kernel:literal,$index:literal,$path:literal,$file:literal) => {
+        EmbeddedKernel { group: $group, kernel: $kernel, index: $index, path: $path, spirv: include_bytes!($file) }
+    };
+}
+// Generated by build.rs from src/shaders (or DXC output with `compile-shaders`), indexed in `#pragma kernel` order.
+pub(crate) static EMBEDDED_KERNELS: &[EmbeddedKernel] = include!(concat!(env!("OUT_DIR"),"/embedded_kernels.rs"));
+macro_rules! software {
+    ($group:literal,$kernel:literal,$file:literal) => {
+        (($group,$kernel),include_bytes!($file).as_slice())
+    };
+}
+// Builds of the ray query kernels compiled without `HardwareRT` (software BVH traversal), used when the adapter has no
+// ray query. Generated by build.rs; empty when it had neither DXC nor checked-in software builds.
+static SOFTWARE_KERNELS: &[((&str,&str),&[u8])] = include!(concat!(env!("OUT_DIR"),"/software_kernels.rs"));
+fn software_shaders() -> HashMap<(&'static str,&'static str),Vec<u8>> {
+    SOFTWARE_KERNELS.iter().map(|(key,spirv)| (*key,spirv.to_vec())).collect()
+}
+const OP_CAPABILITY: u32 = 17;
+const CAPABILITY_RAY_QUERY_KHR: u32 = 4472;
+pub(crate) fn spirv_words(spirv: &[u8]) -> Vec<u32> {
+    spirv.chunks_exact(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+}
+// Walks the SPIR-V instruction stream looking for `OpCapability RayQueryKHR`.
+pub(crate) fn uses_ray_query(spirv: &[u8]) -> bool {
+    let words = spirv_words(spirv);
+    let mut at = 5;
+    while at < words.len() {
+        let count = (words[at] >> 16) as usize;
+        let opcode = words[at] & 0xffff;
+        if opcode == OP_CAPABILITY && words.get(at + 1) == Some(&CAPABILITY_RAY_QUERY_KHR) {
+            return true;
         }
-    }));
-    let shaders = HashMap::<(&str,&str,u32),Vec<u8>>::from([
-        (("IntersectionKernels","kernel_heightmap",1),include_bytes!("shaders/main/IntersectionKernels/kernel_heightmap.spv").to_vec()),
-        (("IntersectionKernels","kernel_shadow",2),include_bytes!("shaders/main/IntersectionKernels/kernel_shadow.spv").to_vec()),
-        (("IntersectionKernels","kernel_shadow_heightmap",3),include_bytes!("shaders/main/IntersectionKernels/kernel_shadow_heightmap.spv").to_vec()),
-        (("IntersectionKernels","kernel_trace",4),include_bytes!("shaders/main/IntersectionKernels/kernel_trace.spv").to_vec()),
-        (("RayGenKernels","CacheCompact",1),include_bytes!("shaders/main/RayGenKernels/CacheCompact.spv").to_vec()),
-        (("RayGenKernels","CacheResolve",2),include_bytes!("shaders/main/RayGenKernels/CacheResolve.spv").to_vec()),
-        (("RayGenKernels","Generate",3),include_bytes!("shaders/main/RayGenKernels/Generate.spv").to_vec()),
-        (("RayGenKernels","GeneratePanorama",4),include_bytes!("shaders/main/RayGenKernels/GeneratePanorama.spv").to_vec()),
-        (("RayTracingShader","kernel_finalize",1),include_bytes!("shaders/main/RayTracingShader/kernel_finalize.spv").to_vec()),
-        (("RayTracingShader","kernel_shade",2),include_bytes!("shaders/main/RayTracingShader/kernel_shade.spv").to_vec()),
-        (("RayTracingShader","MVKernel",3),include_bytes!("shaders/main/RayTracingShader/MVKernel.spv").to_vec()),
-        (("RayTracingShader","OIDNtoTTKernel",4),include_bytes!("shaders/main/RayTracingShader/OIDNtoTTKernel.spv").to_vec()),
-        (("RayTracingShader","RefineMVKernel",5),include_bytes!("shaders/main/RayTracingShader/RefineMVKernel.spv").to_vec()),
-        (("RayTracingShader","ResetMVKernel",6),include_bytes!("shaders/main/RayTracingShader/ResetMVKernel.spv").to_vec()),
-        (("RayTracingShader","TransferKernel",7),include_bytes!("shaders/main/RayTracingShader/TransferKernel.spv").to_vec()),
-        (("RayTracingShader","TTtoOIDNKernel",8),include_bytes!("shaders/main/RayTracingShader/TTtoOIDNKernel.spv").to_vec()),
-        (("RayTracingShader","TTtoOIDNKernelPanorama",9),include_bytes!("shaders/main/RayTracingShader/TTtoOIDNKernelPanorama.spv").to_vec()),
-        (("ReSTIRGI","ReSTIRGIKernel",1),include_bytes!("shaders/main/ReSTIRGI/ReSTIRGIKernel.spv").to_vec()),
-        (("ReSTIRGI","ReSTIRGISpatial",2),include_bytes!("shaders/main/ReSTIRGI/ReSTIRGISpatial.spv").to_vec()),
-        (("ReSTIRGI","ReSTIRGISpatial2",3),include_bytes!("shaders/main/ReSTIRGI/ReSTIRGISpatial2.spv").to_vec()),
-        (("BVHRefitter","BLASLightRefitKernel",1),include_bytes!("shaders/Utility/BVHRefitter/BLASLightRefitKernel.spv").to_vec()),
-        (("BVHRefitter","BLASSGTreeRefitKernel",2),include_bytes!("shaders/Utility/BVHRefitter/BLASSGTreeRefitKernel.spv").to_vec()),
-        (("BVHRefitter","Construct",3),include_bytes!("shaders/Utility/BVHRefitter/Construct.spv").to_vec()),
-        (("BVHRefitter","RefitBVHLayer",4),include_bytes!("shaders/Utility/BVHRefitter/RefitBVHLayer.spv").to_vec()),
-        (("BVHRefitter","RefitLayer",5),include_bytes!("shaders/Utility/BVHRefitter/RefitLayer.spv").to_vec()),
-        (("BVHRefitter","TLASLightBVHRefitKernel",6),include_bytes!("shaders/Utility/BVHRefitter/TLASLightBVHRefitKernel.spv").to_vec()),
-        (("BVHRefitter","TLASSGTreeRefitKernel",7),include_bytes!("shaders/Utility/BVHRefitter/TLASSGTreeRefitKernel.spv").to_vec()),
-        (("BVHRefitter","TransferKernel",8),include_bytes!("shaders/Utility/BVHRefitter/TransferKernel.spv").to_vec()),
-        (("BVHRefitter","UpdateGlobalBufferAABBKernel",9),include_bytes!("shaders/Utility/BVHRefitter/UpdateGlobalBufferAABBKernel.spv").to_vec()),
-        (("CopyTextureShader","BC4Kernel",1),include_bytes!("shaders/Utility/CopyTextureShader/BC4Kernel.spv").to_vec()),
-        (("CopyTextureShader","BC5Kernel",2),include_bytes!("shaders/Utility/CopyTextureShader/BC5Kernel.spv").to_vec()),
-        (("CopyTextureShader","Compress",3),include_bytes!("shaders/Utility/CopyTextureShader/Compress.spv").to_vec()),
-        (("CopyTextureShader","FullKernel",4),include_bytes!("shaders/Utility/CopyTextureShader/FullKernel.spv").to_vec()),
-        (("CopyTextureShader","FullKernelSplit",5),include_bytes!("shaders/Utility/CopyTextureShader/FullKernelSplit.spv").to_vec()),
-        (("CopyTextureShader","HeightmapCompressKernel",6),include_bytes!("shaders/Utility/CopyTextureShader/HeightmapCompressKernel.spv").to_vec()),
-        (("CopyTextureShader","NormalMapKernel",7),include_bytes!("shaders/Utility/CopyTextureShader/NormalMapKernel.spv").to_vec()),
-        (("CopyTextureShader","SingleChannelKernel",8),include_bytes!("shaders/Utility/CopyTextureShader/SingleChannelKernel.spv").to_vec()),
-        (("GeneralMeshFunctions","CombineLightBuffers",1),include_bytes!("shaders/Utility/GeneralMeshFunctions/CombineLightBuffers.spv").to_vec()),
-        (("GeneralMeshFunctions","CombineLightNodes",2),include_bytes!("shaders/Utility/GeneralMeshFunctions/CombineLightNodes.spv").to_vec()),
-        (("GeneralMeshFunctions","CombineNodeBuffers",3),include_bytes!("shaders/Utility/GeneralMeshFunctions/CombineNodeBuffers.spv").to_vec()),
-        (("GeneralMeshFunctions","CombineSGTreeNodes",4),include_bytes!("shaders/Utility/GeneralMeshFunctions/CombineSGTreeNodes.spv").to_vec()),
-        (("GeneralMeshFunctions","CombineTriBuffers",5),include_bytes!("shaders/Utility/GeneralMeshFunctions/CombineTriBuffers.spv").to_vec()),
-    ]);
-    println!("Hello from init!");
-    KERNELS.get_mut().replace(Box::leak(Box::new(Kernels {
-        intersection_kernels: Some(KernelStruct {
-            kernel_name_and_name_to_binding: HashMap::new(),
-            kernel_to_bgl_and_shader_mod: HashMap::new(),
-            name_to_globals_offset: HashMap::new(),
-            kernel_index_to_name: HashMap::new(),
-            globals: Vec::new(),
-        }),
-        raygen_kernels: Some(KernelStruct {
-            kernel_name_and_name_to_binding: HashMap::new(),
-            kernel_to_bgl_and_shader_mod: HashMap::new(),
-            name_to_globals_offset: HashMap::new(),
-            kernel_index_to_name: HashMap::new(),
-            globals: Vec::new(),
-        }),
-        ray_tracing_shader: Some(KernelStruct {
-            kernel_name_and_name_to_binding: HashMap::new(),
-            kernel_to_bgl_and_shader_mod: HashMap::new(),
-            name_to_globals_offset: HashMap::new(),
-            kernel_index_to_name: HashMap::new(),
-globals: Vec::new(),
-        }),
-        restir_gi: Some(KernelStruct {
-            kernel_name_and_name_to_binding: HashMap::new(),
-            kernel_to_bgl_and_shader_mod: HashMap::new(),
-            name_to_globals_offset: HashMap::new(),
-            kernel_index_to_name: HashMap::new(),
-            globals: Vec::new(),
-        }),
-        bvh_refitter: Some(KernelStruct {
-            kernel_name_and_name_to_binding: HashMap::new(),
-            kernel_to_bgl_and_shader_mod: HashMap::new(),
-            name_to_globals_offset: HashMap::new(),
-            kernel_index_to_name: HashMap::new(),
-            globals: Vec::new(),
-        }),
-        copy_texture_shader: Some(KernelStruct {
-            kernel_name_and_name_to_binding: HashMap::new(),
-            kernel_to_bgl_and_shader_mod: HashMap::new(),
-            name_to_globals_offset: HashMap::new(),
-            kernel_index_to_name: HashMap::new(),
-            globals: Vec::new(),
-        }),
-        general_mesh_functions: Some(KernelStruct {
-            kernel_name_and_name_to_binding: HashMap::new(),
-            kernel_to_bgl_and_shader_mod: HashMap::new(),
-            name_to_globals_offset: HashMap::new(),
-            kernel_index_to_name: HashMap::new(),
-            globals: Vec::new(),
-        }),
-    })));
-    let mut binding = DEVICE.get_mut();
-    let (device,queue) = binding.as_mut().unwrap();
-    for ((group,kernel,index), mut shader) in shaders.clone() {
-        let mut binding = KERNELS.get_mut();
-        let kernels_struct = &mut **binding.as_mut().unwrap();
-        let cap = shader.capacity();
-        let len = shader.len();
-        let ptr = shader.as_mut_ptr();
-        forget(shader);
-        let shader = unsafe {Vec::from_raw_parts(ptr as *mut u32,len / 4,cap / 4)};
-        let src_mod = spirv_cross2::Module::from_words(&shader);
-        let mut compiler = spirv_cross2::Compiler::<spirv_cross2::targets::Hlsl>::new(src_mod).unwrap();
-        let mut options = spirv_cross2::targets::Hlsl::options();
-        options.shader_model = HlslShaderModel::ShaderModel6_8;
-        options.enable_16bit_types = true;
-        println!("module_reflect");
-
-        let module_reflect = spirv_reflect::ShaderModule::load_u32_data(&shader).unwrap();
-        let bindings = module_reflect.enumerate_descriptor_bindings(None).unwrap();
-        let mut entries = Vec::<wgpu::BindGroupLayoutEntry>::new();
-        let new_src = compiler.compile(&options).unwrap().to_string();
-        let local_size = module_reflect.enumerate_entry_points().unwrap().first().unwrap().local_size;
-        let shader_mod = unsafe {device.create_shader_module_passthrough(ShaderModuleDescriptorPassthrough {
-            entry_point: module_reflect.get_entry_point_name(),
-            label: None,
-            hlsl: Some(Cow::from(new_src.as_str())),
-            ..Default::default()
-        })};
-        println!("Loading shader {:#?}",kernel);
-       // println!("shader_mod: {:#?}",shader_mod);
-        let mut global_binding: u32 = 0;
-        let kernel_struct = match group {
-            "IntersectionKernels" => kernels_struct.intersection_kernels.as_mut().unwrap(),
-            "RayGenKernels" => kernels_struct.raygen_kernels.as_mut().unwrap(),
-            "RayTracingShader" => kernels_struct.ray_tracing_shader.as_mut().unwrap(),
-            "ReSTIRGI" => kernels_struct.restir_gi.as_mut().unwrap(),
-            "BVHRefitter" => kernels_struct.bvh_refitter.as_mut().unwrap(),
-            "CopyTextureShader" => kernels_struct.copy_texture_shader.as_mut().unwrap(),
-            "GeneralMeshFunctions" => kernels_struct.general_mesh_functions.as_mut().unwrap(),
-            _ => unreachable!()
+        if count == 0 {
+            break;
+        }
+        at += count;
+    }
+    false
+}
+// Reflection only, so it can run before there is a device to size the limits for.
+pub(crate) fn kernel_binding_counts() -> Vec<(String,BindingCounts)> {
+    let software = software_shaders().into_iter().map(|((group,kernel),shader)| (format!("{}/{} (software BVH)",group,kernel),shader));
+    shader_loader::load().into_iter().map(|((group,kernel,_),shader)| (format!("{}/{}",group,kernel),shader))
+        .chain(software)
+        .filter_map(|(name,shader)| match count_bindings(&spirv_words(&shader)) {
+            Ok(counts) => Some((name,counts)),
+            Err(err) => {
+                log::error!("Could not reflect {}: {}",name,err);
+                None
+            }
+        })
+        .collect()
+}
+// What reflection gives us about a kernel: enough to set its properties before it is compiled.
+pub struct ReflectedKernel {
+    pub words: Vec<u32>,
+    pub entry_point: String,
+    pub entries: Vec<BindGroupLayoutEntry>,
+    pub bindings: Vec<(String,u32)>,
+    // $Globals members as (name, offset, size).
+    pub globals: Vec<(String,u32,u32)>,
+}
+pub(crate) struct BuiltKernel {
+    pub reflected: ReflectedKernel,
+    // None in lazy mode until the kernel is first dispatched or prewarmed.
+    pub compiled: Option<(BindGroupLayout,ShaderModule)>,
+}
+impl KernelStruct {
+    pub(crate) fn insert_kernel(&mut self,kernel: &str,built: BuiltKernel) {
+        for (name,offset,size) in &built.reflected.globals {
+            // Uniform buffers are sized in 16 byte rows.
+            let end = ((offset + size) as usize).next_multiple_of(16);
+            if self.globals.len() < end {
+                self.globals.resize(end,0);
+            }
+            self.name_to_globals_offset.insert(name.clone(),*offset);
+            self.name_to_globals_size.insert(name.clone(),*size);
+        }
+        for (name,binding) in &built.reflected.bindings {
+            self.kernel_name_and_name_to_binding.insert((kernel.to_string(),name.clone()),*binding);
+        }
+        match built.compiled {
+            Some(compiled) => {
+                self.lazy_kernels.remove(kernel);
+                self.kernel_to_bgl_and_shader_mod.insert(kernel.to_string(),compiled);
+            },
+            None => {
+                self.kernel_to_bgl_and_shader_mod.remove(kernel);
+                self.lazy_kernels.insert(kernel.to_string(),built.reflected);
+            },
+        }
+    }
+    // Compiles a lazy kernel on first use. A kernel that fails is dropped, so the error is only logged once.
+    pub(crate) fn ensure_compiled(&mut self,device: &wgpu::Device,backend: Backend,kernel: &str) -> bool {
+        if self.kernel_to_bgl_and_shader_mod.contains_key(kernel) {
+            return true;
+        }
+        let Some(reflected) = self.lazy_kernels.remove(kernel) else {
+            return false;
         };
-        kernel_struct.kernel_index_to_name.insert(index,String::from(kernel));
-        for reflect_binding in bindings {
-            if reflect_binding.name == "$Globals" {
-                global_binding = reflect_binding.binding;
-              //  println!("$Global has {:#?} members",reflect_binding.block.members.len());
-                for member in reflect_binding.block.members {
-                    kernel_struct.name_to_globals_offset.insert(member.name.clone(), member.offset);
-                    for _ in 0..member.size {
-                        kernel_struct.globals.push(0);
-                    }
+        match compile_kernel(device,backend,kernel,&reflected) {
+            Ok(compiled) => {
+                log::debug!("Compiled kernel {} on first use",kernel);
+                self.kernel_to_bgl_and_shader_mod.insert(kernel.to_string(),compiled);
+                true
+            },
+            Err(err) => {
+                log::error!("Could not compile {}, it won't dispatch: {}",kernel,err);
+                false
+            }
+        }
+    }
+    // Compiles the given lazy kernels in parallel. Returns false if any of them failed.
+    pub(crate) fn prewarm(&mut self,device: &wgpu::Device,backend: Backend,kernels: &[String]) -> bool {
+        let pending = kernels.iter().filter_map(|kernel| self.lazy_kernels.remove_entry(kernel)).collect::<Vec<_>>();
+        let compiled = pending.into_par_iter().map(|(kernel,reflected)| {
+            let result = compile_kernel(device,backend,&kernel,&reflected);
+            (kernel,result)
+        }).collect::<Vec<_>>();
+        let mut all_ok = true;
+        for (kernel,result) in compiled {
+            match result {
+                Ok(compiled) => {
+                    self.kernel_to_bgl_and_shader_mod.insert(kernel,compiled);
+                },
+                Err(err) => {
+                    log::error!("Could not compile {}, it won't dispatch: {}",kernel,err);
+                    all_ok = false;
+                }
+            }
+        }
+        all_ok && kernels.iter().all(|kernel| self.kernel_to_bgl_and_shader_mod.contains_key(kernel))
+    }
+    // Swaps in a rebuilt kernel. Properties the new build still declares with the same size keep their values,
+    // even if their offset moved; everything else starts from zero.
+    pub(crate) fn replace_kernel(&mut self,kernel: &str,built: BuiltKernel) {
+        let previous = self.globals.clone();
+        let (mut kept,mut reset) = (0,0);
+        for (name,offset,size) in &built.reflected.globals {
+            let (offset,end) = (*offset as usize,(offset + size) as usize);
+            if self.globals.len() < end {
+                self.globals.resize(end,0);
+            }
+            match (self.name_to_globals_offset.get(name),self.name_to_globals_size.get(name)) {
+                (Some(&old_offset),Some(old_size)) if old_size == size => {
+                    let old_offset = old_offset as usize;
+                    self.globals[offset..end].copy_from_slice(&previous[old_offset..old_offset + *size as usize]);
+                    kept += 1;
+                },
+                _ => {
+                    self.globals[offset..end].fill(0);
+                    reset += 1;
+                },
+            }
+        }
+        self.kernel_name_and_name_to_binding.retain(|(name,_),_| name != kernel);
+        log::info!("Reloaded kernel {}: kept {} property values, reset {}",kernel,kept,reset);
+        self.insert_kernel(kernel,built);
+    }
+}
+
+pub(crate) fn current_backend() -> Backend {
+    ADAPTER_INFO.get_mut().as_ref().map_or(Backend::Dx12,|info| info.backend)
+}
+pub const INIT_NOT_STARTED: u32 = 0;
+pub const INIT_RUNNING: u32 = 1;
+pub const INIT_DONE: u32 = 2;
+// Init panicked part way; KERNELS keeps whatever it held before.
+pub const INIT_FAILED: u32 = 3;
+static INIT_STATE: AtomicU32 = AtomicU32::new(INIT_NOT_STARTED);
+static INIT_KERNELS_DONE: AtomicU32 = AtomicU32::new(0);
+static INIT_KERNELS_TOTAL: AtomicU32 = AtomicU32::new(0);
+
+// Marks init as failed if build_kernels unwinds before it gets to INIT_DONE, so a loading bar doesn't wait forever.
+struct InitGuard;
+impl Drop for InitGuard {
+    fn drop(&mut self) {
+        if INIT_STATE.compare_exchange(INIT_RUNNING,INIT_FAILED,Ordering::SeqCst,Ordering::SeqCst).is_ok() {
+            log::error!("Kernel init failed, see the crash report");
+        }
+    }
+}
+
+// Built once and reused by rebuilds after device recovery. None if the threads couldn't be spawned, in which case
+// kernels are built on the calling thread.
+fn shader_pool() -> Option<&'static rayon::ThreadPool> {
+    static POOL: OnceLock<Option<rayon::ThreadPool>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        rayon::ThreadPoolBuilder::new()
+            .thread_name(|i| format!("truetrace-shaders-{}",i))
+            .build()
+            .inspect_err(|err| log::warn!("Could not start the shader worker pool, building kernels on one thread: {}",err))
+            .ok()
+    }).as_ref()
+}
+
+// Kernels are built on a worker pool from a clone of the device, then merged into a fresh registry that replaces
+// KERNELS only once everything is done, so nothing is locked while DXC runs.
+fn build_kernels(shaders: HashMap<(&'static str,&'static str,u32),Vec<u8>>) {
+    log::info!("Loading {} kernels",shaders.len());
+    INIT_KERNELS_DONE.store(0,Ordering::SeqCst);
+    INIT_KERNELS_TOTAL.store(shaders.len() as u32,Ordering::SeqCst);
+    INIT_STATE.store(INIT_RUNNING,Ordering::SeqCst);
+    let _guard = InitGuard;
+    let device = DEVICE.get_mut().as_ref().unwrap().0.clone();
+    let backend = current_backend();
+    let mut software = software_shaders();
+    let has_ray_query = has_capability(CAPABILITY_RAY_QUERY);
+    let lazy = CONFIG.get_mut().shaders.lazy;
+    let mut kernels = Kernels::default();
+    let mut jobs = Vec::new();
+    for ((group,kernel,index), mut shader) in shaders {
+        kernels.groups.entry(group.to_string()).or_default().kernel_index_to_name.insert(index,String::from(kernel));
+        if !has_ray_query && uses_ray_query(&shader) {
+            match software.remove(&(group,kernel)) {
+                Some(bytes) => {
+                    log::info!("Using the software BVH build of {}/{}",group,kernel);
+                    add_capability(CAPABILITY_SOFTWARE_BVH);
+                    shader = bytes;
+                },
+                None => {
+                    log::error!("{}/{} needs ray query and has no software BVH build, it won't dispatch",group,kernel);
+                    INIT_KERNELS_DONE.fetch_add(1,Ordering::SeqCst);
+                    continue;
                 }
             }
-            entries.push(BindGroupLayoutEntry {
-                binding: reflect_binding.binding,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                count: None,
-                ty: match reflect_binding.descriptor_type {
-                    ReflectDescriptorType::StorageBuffer => wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Storage {
-                            read_only: reflect_binding.type_description.unwrap().decoration_flags.contains(ReflectDecorationFlags::NON_WRITABLE),
-                        },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    ReflectDescriptorType::AccelerationStructureKHR => wgpu::BindingType::AccelerationStructure {
-                        vertex_return: false,
-                    },
-                    ReflectDescriptorType::CombinedImageSampler => unimplemented!(),
-                    ReflectDescriptorType::SampledImage => wgpu::BindingType::Texture {
-                        view_dimension: match (reflect_binding.image.dim, reflect_binding.image.arrayed != 0) {
-                            (spirv_reflect::types::ReflectDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
-                            (spirv_reflect::types::ReflectDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
-                            (spirv_reflect::types::ReflectDimension::Type1d, false) => wgpu::TextureViewDimension::D1,
-                            (spirv_reflect::types::ReflectDimension::Type2d, false) => wgpu::TextureViewDimension::D2,
-                            (spirv_reflect::types::ReflectDimension::Type2d, true) => wgpu::TextureViewDimension::D2Array,
-                            (spirv_reflect::types::ReflectDimension::Type3d, false) => wgpu::TextureViewDimension::D3,
-                            _ => unimplemented!()
-                        },
-                        sample_type: {
-                            let flags = reflect_binding.type_description.unwrap().type_flags;
-                            if flags.contains(ReflectTypeFlags::FLOAT) {
-                                TextureSampleType::Float { filterable: false }
-                            } else if flags.contains(ReflectTypeFlags::INT) {
-                                TextureSampleType::Sint
-                            } else {
-                                unimplemented!()
-                            }
-                        },
-                        multisampled: reflect_binding.image.ms != 0,
-                    },
-                    ReflectDescriptorType::Sampler => wgpu::BindingType::Sampler(SamplerBindingType::NonFiltering),
-                    ReflectDescriptorType::StorageBufferDynamic => wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Storage {
-                            read_only: reflect_binding.type_description.unwrap().decoration_flags.contains(ReflectDecorationFlags::NON_WRITABLE),
-                        },
-                        has_dynamic_offset: true,
-                        min_binding_size: None,
-                    },
-                    ReflectDescriptorType::StorageImage => wgpu::BindingType::StorageTexture {
-                        access: match reflect_binding.type_description.unwrap().decoration_flags.contains(ReflectDecorationFlags::NON_WRITABLE) {
-                            true => wgpu::StorageTextureAccess::ReadOnly,
-                            false => wgpu::StorageTextureAccess::ReadWrite,
-                        },
-                        format: convert_reflection_image_fmt_to_wgpu(reflect_binding.image.image_format),
-                        view_dimension: match (reflect_binding.image.dim, reflect_binding.image.arrayed != 0) {
-                            (spirv_reflect::types::ReflectDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
-                            (spirv_reflect::types::ReflectDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
-                            (spirv_reflect::types::ReflectDimension::Type1d, false) => wgpu::TextureViewDimension::D1,
-                            (spirv_reflect::types::ReflectDimension::Type2d, false) => wgpu::TextureViewDimension::D2,
-                            (spirv_reflect::types::ReflectDimension::Type2d, true) => wgpu::TextureViewDimension::D2Array,
-                            (spirv_reflect::types::ReflectDimension::Type3d, false) => wgpu::TextureViewDimension::D3,
-                            _ => unimplemented!()
-                        },
-                    },
-                    ReflectDescriptorType::StorageTexelBuffer => wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Storage {
-                            read_only: reflect_binding.type_description.unwrap().decoration_flags.contains(ReflectDecorationFlags::NON_WRITABLE),
-                        },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    ReflectDescriptorType::UniformBuffer => wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    ReflectDescriptorType::UniformBufferDynamic => wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: true,
-                        min_binding_size: None,
-                    },
-                    ReflectDescriptorType::UniformTexelBuffer => wgpu::BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    _ => unimplemented!()
+        }
+        jobs.push((group,kernel,shader));
+    }
+    let build = |(group,kernel,shader): (&'static str,&'static str,Vec<u8>)| {
+        let result = if lazy {
+            reflect_kernel(&shader).map(|reflected| BuiltKernel { reflected, compiled: None })
+        } else {
+            build_kernel(&device,backend,kernel,&shader)
+        };
+        INIT_KERNELS_DONE.fetch_add(1,Ordering::SeqCst);
+        (group,kernel,result)
+    };
+    let built = match shader_pool() {
+        Some(pool) => pool.install(|| jobs.into_par_iter().map(build).collect::<Vec<_>>()),
+        None => jobs.into_iter().map(build).collect::<Vec<_>>(),
+    };
+    for (group,kernel,result) in built {
+        match result {
+            Ok(built) => {
+                kernels.groups.get_mut(group).unwrap().insert_kernel(kernel,built);
+                log::debug!("Loaded kernel {}/{}",group,kernel);
+            },
+            Err(err) => log::error!("Could not build {}/{}: {}",group,kernel,err),
+        }
+    }
+    KERNELS.get_mut().replace(Box::leak(Box::new(kernels)));
+    INIT_STATE.store(INIT_DONE,Ordering::SeqCst);
+}
+
+// For a loading bar: init can run on a background thread while the game polls this. `done`/`total` count kernels
+// and may be null. Returns INIT_NOT_STARTED, INIT_RUNNING, INIT_DONE or INIT_FAILED.
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_GetInitProgress(done: *mut u32,total: *mut u32) -> u32 {
+    if !done.is_null() {
+        unsafe {done.write(INIT_KERNELS_DONE.load(Ordering::SeqCst))};
+    }
+    if !total.is_null() {
+        unsafe {total.write(INIT_KERNELS_TOTAL.load(Ordering::SeqCst))};
+    }
+    INIT_STATE.load(Ordering::SeqCst)
+}
+
+// Reflection, bind group layout and module for one kernel.
+pub(crate) fn build_kernel(device: &wgpu::Device,backend: Backend,kernel: &str,spirv: &[u8]) -> Result<BuiltKernel,String> {
+    let reflected = reflect_kernel(spirv)?;
+    let compiled = compile_kernel(device,backend,kernel,&reflected)?;
+    Ok(BuiltKernel { reflected, compiled: Some(compiled) })
+}
+
+pub(crate) fn reflect_kernel(spirv: &[u8]) -> Result<ReflectedKernel,String> {
+    let words = spirv_words(spirv);
+    let module_reflect = spirv_reflect::ShaderModule::load_u32_data(&words).map_err(|e| e.to_string())?;
+    let mut entries = Vec::new();
+    let mut bindings = Vec::new();
+    let mut globals = Vec::new();
+    for reflect_binding in module_reflect.enumerate_descriptor_bindings(None).map_err(|e| e.to_string())? {
+        if reflect_binding.name == "$Globals" {
+            for member in &reflect_binding.block.members {
+                globals.push((member.name.clone(),member.offset,member.size));
+            }
+        }
+        entries.push(layout_entry(&reflect_binding)?);
+        bindings.push((reflect_binding.name,reflect_binding.binding));
+    }
+    let entry_point = module_reflect.get_entry_point_name();
+    Ok(ReflectedKernel { words, entry_point, entries, bindings, globals })
+}
+
+// Translation, module and layout creation. Validation runs inside an error scope so a bad kernel comes back as an Err
+// instead of going to the uncaptured error handler.
+pub(crate) fn compile_kernel(device: &wgpu::Device,backend: Backend,kernel: &str,reflected: &ReflectedKernel) -> Result<(BindGroupLayout,ShaderModule),String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = create_shader_module(device,backend,&reflected.words,&reflected.entry_point);
+    let bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        entries: &reflected.entries,
+        label: Some(format!("bgl for {}",kernel).as_str()),
+    });
+    let validation = crate::runtime::block_on(device.pop_error_scope());
+    let module = module?;
+    if let Some(err) = validation {
+        return Err(err.to_string());
+    }
+    Ok((bgl,module))
+}
+
+fn view_dimension(image: &ReflectImageTraits) -> Result<wgpu::TextureViewDimension,String> {
+    Ok(match (image.dim, image.arrayed != 0) {
+        (ReflectDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+        (ReflectDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+        (ReflectDimension::Type1d, false) => wgpu::TextureViewDimension::D1,
+        (ReflectDimension::Type2d, false) => wgpu::TextureViewDimension::D2,
+        (ReflectDimension::Type2d, true) => wgpu::TextureViewDimension::D2Array,
+        (ReflectDimension::Type3d, false) => wgpu::TextureViewDimension::D3,
+        (dim, arrayed) => return Err(format!("unsupported image dimension {:?} (arrayed: {})",dim,arrayed)),
+    })
+}
+fn layout_entry(reflect_binding: &ReflectDescriptorBinding) -> Result<BindGroupLayoutEntry,String> {
+    let read_only = reflect_binding.type_description.as_ref().is_some_and(|ty| ty.decoration_flags.contains(ReflectDecorationFlags::NON_WRITABLE));
+    let storage_buffer = |has_dynamic_offset| wgpu::BindingType::Buffer {
+        ty: BufferBindingType::Storage { read_only },
+        has_dynamic_offset,
+        min_binding_size: None,
+    };
+    let uniform_buffer = |has_dynamic_offset| wgpu::BindingType::Buffer {
+        ty: BufferBindingType::Uniform,
+        has_dynamic_offset,
+        min_binding_size: None,
+    };
+    let ty = match reflect_binding.descriptor_type {
+        ReflectDescriptorType::StorageBuffer | ReflectDescriptorType::StorageTexelBuffer => storage_buffer(false),
+        ReflectDescriptorType::StorageBufferDynamic => storage_buffer(true),
+        ReflectDescriptorType::UniformBuffer | ReflectDescriptorType::UniformTexelBuffer => uniform_buffer(false),
+        ReflectDescriptorType::UniformBufferDynamic => uniform_buffer(true),
+        ReflectDescriptorType::AccelerationStructureKHR => wgpu::BindingType::AccelerationStructure {
+            vertex_return: false,
+        },
+        ReflectDescriptorType::SampledImage => wgpu::BindingType::Texture {
+            view_dimension: view_dimension(&reflect_binding.image)?,
+            sample_type: {
+                let flags = reflect_binding.type_description.as_ref().map(|ty| ty.type_flags).unwrap_or(ReflectTypeFlags::UNDEFINED);
+                if flags.contains(ReflectTypeFlags::FLOAT) {
+                    TextureSampleType::Float { filterable: false }
+                } else if flags.contains(ReflectTypeFlags::INT) {
+                    TextureSampleType::Sint
+                } else {
+                    return Err(format!("{}: unsupported texture sample type {:?}",reflect_binding.name,flags));
+                }
+            },
+            multisampled: reflect_binding.image.ms != 0,
+        },
+        ReflectDescriptorType::Sampler => wgpu::BindingType::Sampler(SamplerBindingType::NonFiltering),
+        ReflectDescriptorType::StorageImage => wgpu::BindingType::StorageTexture {
+            access: if read_only { wgpu::StorageTextureAccess::ReadOnly } else { wgpu::StorageTextureAccess::ReadWrite },
+            format: convert_reflection_image_fmt_to_wgpu(reflect_binding.image.image_format),
+            view_dimension: view_dimension(&reflect_binding.image)?,
+        },
+        other => return Err(format!("{}: unsupported descriptor type {:?}",reflect_binding.name,other)),
+    };
+    Ok(BindGroupLayoutEntry {
+        binding: reflect_binding.binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        count: None,
+        ty,
+    })
+}
+fn translate_hlsl(spirv: &[u32]) -> Result<String,String> {
+    let mut options = spirv_cross2::targets::Hlsl::options();
+    options.shader_model = HlslShaderModel::ShaderModel6_8;
+    options.enable_16bit_types = true;
+    // The whole options struct goes into the cache key, so changing any option misses instead of reusing stale HLSL.
+    let key = shader_cache::hlsl_key(spirv,&format!("{:?}",options));
+    if let Some(hlsl) = shader_cache::load_hlsl(key) {
+        return Ok(hlsl);
+    }
+    let src_mod = spirv_cross2::Module::from_words(spirv);
+    let mut compiler = spirv_cross2::Compiler::<spirv_cross2::targets::Hlsl>::new(src_mod).map_err(|e| e.to_string())?;
+    let hlsl = compiler.compile(&options).map_err(|e| e.to_string())?.to_string();
+    shader_cache::store_hlsl(key,&hlsl);
+    Ok(hlsl)
+}
+// Matches the shader model and 16-bit types the HLSL is translated for.
+const DXIL_TARGET: &str = "cs_6_8";
+fn compile_dxil(hlsl: &str,entry_point: &str) -> Result<Vec<u8>,String> {
+    let args = ["-E", entry_point, "-T", DXIL_TARGET, "-enable-16bit-types", "-O3"];
+    let key = shader_cache::dxil_key(hlsl,&args.join(" "));
+    if let Some(dxil) = shader_cache::load_dxil(key) {
+        return Ok(dxil);
+    }
+    let dxil = run_dxc(hlsl,&args)?;
+    shader_cache::store_dxil(key,&dxil);
+    Ok(dxil)
+}
+fn run_dxc(hlsl: &str,args: &[&str]) -> Result<Vec<u8>,String> {
+    #[cfg(windows)]
+    return crate::dxil::compile(hlsl,args);
+    #[cfg(not(windows))]
+    Err(String::from("DXIL can only be compiled on Windows"))
+}
+fn create_shader_module(device: &wgpu::Device,backend: Backend,spirv: &[u32],entry_point: &str) -> Result<ShaderModule,String> {
+    let module = match backend {
+        // Without passthrough support the SPIR-V goes through naga instead.
+        Backend::Dx12 if has_capability(CAPABILITY_PASSTHROUGH_SHADERS) => {
+            let new_src = translate_hlsl(spirv)?;
+            let descriptor = match compile_dxil(&new_src,entry_point) {
+                Ok(dxil) => ShaderModuleDescriptorPassthrough {
+                    entry_point: entry_point.to_string(),
+                    label: None,
+                    dxil: Some(Cow::from(dxil)),
+                    ..Default::default()
                 },
-            });
-            kernel_struct.kernel_name_and_name_to_binding.insert((kernel.to_string(), reflect_binding.name), reflect_binding.binding);
+                // wgpu compiles the HLSL itself instead, it just can't be cached.
+                Err(err) => {
+                    log::warn!("{}: could not compile DXIL, passing HLSL to wgpu: {}",entry_point,err);
+                    ShaderModuleDescriptorPassthrough {
+                        entry_point: entry_point.to_string(),
+                        label: None,
+                        hlsl: Some(Cow::from(new_src.as_str())),
+                        ..Default::default()
+                    }
+                }
+            };
+            unsafe {device.create_shader_module_passthrough(descriptor)}
+        },
+        Backend::Vulkan if has_capability(CAPABILITY_PASSTHROUGH_SHADERS) => unsafe {device.create_shader_module_passthrough(ShaderModuleDescriptorPassthrough {
+            entry_point: entry_point.to_string(),
+            label: None,
+            spirv: Some(Cow::from(spirv)),
+            ..Default::default()
+        })},
+        _ => device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::SpirV(Cow::from(spirv)),
+        }),
+    };
+    let info = crate::runtime::block_on(module.get_compilation_info());
+    let mut errors = Vec::new();
+    for message in info.messages {
+        match message.message_type {
+            CompilationMessageType::Error => errors.push(message.message),
+            CompilationMessageType::Warning => log::warn!("{}: {}",entry_point,message.message),
+            CompilationMessageType::Info => log::info!("{}: {}",entry_point,message.message),
         }
-        let bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            entries: &entries,
-            label: Some(format!("bgl for {}",kernel).as_str()),
-        });
-        kernel_struct.kernel_to_bgl_and_shader_mod.insert(String::from(kernel), (bgl, shader_mod));
-        // LOG_FILE.get_mut().as_ref().unwrap().write("Worked I guess!".as_bytes()).unwrap();
     }
+    if errors.is_empty() { Ok(module) } else { Err(errors.join("\n")) }
 }
 fn convert_reflection_image_fmt_to_wgpu(fmt: ReflectImageFormat) -> wgpu::TextureFormat {
     match fmt {
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::task::JoinHandle;
+
+// One worker thread for everything async in the plugin: device requests, shader compilation info and device polling.
+static RUNTIME: Mutex<Option<Runtime>> = Mutex::new(None);
+static POLLER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+const POLL_INTERVAL: Duration = Duration::from_millis(4);
+
+// Starts the runtime on first use, including after a shutdown.
+pub fn handle() -> Handle {
+    let mut runtime = RUNTIME.lock().unwrap();
+    runtime.get_or_insert_with(|| {
+        log::debug!("Starting the TrueTrace runtime");
+        Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("truetrace-runtime")
+            .enable_time()
+            .build()
+            .unwrap()
+    }).handle().clone()
+}
+
+// Must not be called from the runtime thread itself.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    handle().block_on(future)
+}
+
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) -> JoinHandle<()> {
+    handle().spawn(future)
+}
+
+// Keeps map_async callbacks, readbacks and the device-lost callback firing without C# having to poll.
+// Replaces any poller for a previous device.
+pub fn start_polling(device: wgpu::Device) {
+    let poller = spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            if let Err(err) = device.poll(wgpu::PollType::Poll) {
+                log::warn!("Device poll failed: {}",err);
+            }
+        }
+    });
+    if let Some(previous) = POLLER.lock().unwrap().replace(poller) {
+        previous.abort();
+    }
+}
+pub fn stop_polling() {
+    if let Some(poller) = POLLER.lock().unwrap().take() {
+        poller.abort();
+    }
+}
+
+// `wait` joins the worker thread; pass false under the loader lock, where a thread can't exit.
+pub fn shutdown(wait: bool) {
+    stop_polling();
+    let Some(runtime) = RUNTIME.lock().unwrap().take() else {
+        return;
+    };
+    if wait {
+        runtime.shutdown_timeout(Duration::from_secs(2));
+    } else {
+        runtime.shutdown_background();
+    }
+    log::debug!("TrueTrace runtime stopped");
+}
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use wgpu::{AdapterInfo, Device, Features, PipelineCache, PipelineCacheDescriptor};
+use crate::config::CONFIG;
+use crate::shader_loader::fnv1a;
+
+// Translated HLSL under <dir>/hlsl/<key>.hlsl, its DXIL under <dir>/dxil/<key>.dxil, and the wgpu pipeline cache blob
+// under <dir>/<adapter key>.
+// Keys hash every input, so a changed kernel, option or compiler just misses and the stale file is never read.
+static PIPELINE_CACHE: Mutex<Option<(PipelineCache,PathBuf)>> = Mutex::new(None);
+
+// spirv-cross version from Cargo.lock (set by build.rs), plus the size and timestamp of the DXC library wgpu loads,
+// which is as close to a version as we can get without loading it.
+static COMPILER_VERSION: LazyLock<String> = LazyLock::new(|| {
+    let dxc = CONFIG.get_mut().dxc_path.clone();
+    let dxc = std::fs::metadata(&dxc).ok()
+        .map(|meta| format!("{} {} {:?}",dxc,meta.len(),meta.modified().ok()))
+        .unwrap_or_else(|| format!("{} (not found)",dxc));
+    format!("spirv-cross2 {}; {}",env!("TRUETRACE_SPIRV_CROSS_VERSION"),dxc)
+});
+
+// Read once: kernels are translated on worker threads, which shouldn't each go through CONFIG.
+static CACHE_DIR: LazyLock<Option<PathBuf>> = LazyLock::new(|| CONFIG.get_mut().shaders.cache_dir.clone());
+
+fn cache_dir() -> Option<PathBuf> {
+    CACHE_DIR.clone()
+}
+
+pub fn hlsl_key(spirv: &[u32],options: &str) -> u64 {
+    let mut bytes = bytemuck::cast_slice::<u32,u8>(spirv).to_vec();
+    bytes.extend_from_slice(options.as_bytes());
+    bytes.extend_from_slice(COMPILER_VERSION.as_bytes());
+    fnv1a(&bytes)
+}
+
+pub fn dxil_key(hlsl: &str,args: &str) -> u64 {
+    let mut bytes = hlsl.as_bytes().to_vec();
+    bytes.extend_from_slice(args.as_bytes());
+    bytes.extend_from_slice(COMPILER_VERSION.as_bytes());
+    fnv1a(&bytes)
+}
+
+fn hlsl_path(key: u64) -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("hlsl").join(format!("{:016x}.hlsl",key)))
+}
+
+pub fn load_hlsl(key: u64) -> Option<String> {
+    std::fs::read_to_string(hlsl_path(key)?).ok()
+}
+
+pub fn store_hlsl(key: u64,hlsl: &str) {
+    let Some(path) = hlsl_path(key) else {
+        return;
+    };
+    if let Err(err) = write_atomic(&path,hlsl.as_bytes()) {
+        log::warn!("Could not cache HLSL at {}: {}",path.display(),err);
+    }
+}
+
+fn dxil_path(key: u64) -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("dxil").join(format!("{:016x}.dxil",key)))
+}
+
+pub fn load_dxil(key: u64) -> Option<Vec<u8>> {
+    std::fs::read(dxil_path(key)?).ok()
+}
+
+pub fn store_dxil(key: u64,dxil: &[u8]) {
+    let Some(path) = dxil_path(key) else {
+        return;
+    };
+    if let Err(err) = write_atomic(&path,dxil) {
+        log::warn!("Could not cache DXIL at {}: {}",path.display(),err);
+    }
+}
+
+// Written next to the target and renamed over it, so a crash mid-write never leaves a truncated entry.
+fn write_atomic(path: &Path,contents: &[u8]) -> std::io::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let temp = path.with_extension("tmp");
+    std::fs::write(&temp,contents)?;
+    std::fs::rename(&temp,path)
+}
+
+// wgpu only implements pipeline caches on Vulkan; elsewhere this does nothing and pipelines are created uncached.
+pub fn open_pipeline_cache(device: &Device,info: &AdapterInfo) {
+    if !device.features().contains(Features::PIPELINE_CACHE) {
+        return;
+    }
+    let (Some(dir),Some(key)) = (cache_dir(),wgpu::util::pipeline_cache_key(info)) else {
+        return;
+    };
+    let path = dir.join(key);
+    let data = std::fs::read(&path).ok();
+    // With fallback set, data from another driver or wgpu version is discarded instead of failing.
+    let cache = unsafe {device.create_pipeline_cache(&PipelineCacheDescriptor {
+        label: Some("TrueTrace pipeline cache"),
+        data: data.as_deref(),
+        fallback: true,
+    })};
+    log::info!("Pipeline cache {} ({})",path.display(),data.map_or(String::from("empty"),|data| format!("{} bytes",data.len())));
+    PIPELINE_CACHE.lock().unwrap().replace((cache,path));
+}
+
+pub fn with_pipeline_cache<R>(f: impl FnOnce(Option<&PipelineCache>) -> R) -> R {
+    let cache = PIPELINE_CACHE.lock().unwrap();
+    f(cache.as_ref().map(|(cache,_)| cache))
+}
+
+// `save` is false after device loss, when the cache contents can't be trusted.
+pub fn close_pipeline_cache(save: bool) {
+    let Some((cache,path)) = PIPELINE_CACHE.lock().unwrap().take() else {
+        return;
+    };
+    if !save {
+        return;
+    }
+    if let Some(data) = cache.get_data() {
+        match write_atomic(&path,&data) {
+            Ok(()) => log::info!("Saved {} bytes of pipeline cache to {}",data.len(),path.display()),
+            Err(err) => log::warn!("Could not save the pipeline cache to {}: {}",path.display(),err),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+use crate::config::CONFIG;
+use crate::render::{EmbeddedKernel, EMBEDDED_KERNELS};
+
+const SPIRV_MAGIC: u32 = 0x07230203;
+
+// Stable across builds, unlike DefaultHasher, so the logged hashes can be compared between runs.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325,|hash,byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpirvHeader {
+    pub major: u8,
+    pub minor: u8,
+    pub generator: u32,
+}
+pub fn spirv_header(spirv: &[u8]) -> Option<SpirvHeader> {
+    if spirv.len() < 20 || spirv.len() % 4 != 0 {
+        return None;
+    }
+    let word = |at: usize| u32::from_le_bytes(spirv[at * 4..at * 4 + 4].try_into().unwrap());
+    if word(0) != SPIRV_MAGIC {
+        return None;
+    }
+    Some(SpirvHeader {
+        major: (word(1) >> 16) as u8,
+        minor: (word(1) >> 8) as u8,
+        generator: word(2),
+    })
+}
+
+// (path, hash) pairs already reported, so a file is only logged again when its contents change.
+static REPORTED: LazyLock<Mutex<HashSet<(String,u64)>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+fn read_override(dir: &Path,kernel: &EmbeddedKernel) -> Option<Vec<u8>> {
+    let path = dir.join(kernel.path);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            log::warn!("Could not read {}, using the embedded kernel: {}",path.display(),err);
+            return None;
+        }
+    };
+    let Some(header) = spirv_header(&bytes) else {
+        log::warn!("{} is not SPIR-V, using the embedded kernel",path.display());
+        return None;
+    };
+    let hash = fnv1a(&bytes);
+    if REPORTED.lock().unwrap().insert((kernel.path.to_string(),hash)) {
+        let embedded_hash = fnv1a(kernel.spirv);
+        if hash == embedded_hash {
+            log::debug!("{} on disk matches the embedded copy",kernel.path);
+        } else {
+            let embedded = spirv_header(kernel.spirv);
+            log::info!("Overriding {} from {}: SPIR-V {}.{} (generator {:#010x}) hash {:016x}, embedded is {} hash {:016x}",
+                kernel.path,path.display(),header.major,header.minor,header.generator,hash,
+                embedded.map_or(String::from("invalid"),|e| format!("SPIR-V {}.{} (generator {:#010x})",e.major,e.minor,e.generator)),embedded_hash);
+        }
+    }
+    Some(bytes)
+}
+
+// Every embedded kernel, each replaced by its copy under the configured shader directory when there is one.
+pub fn load() -> HashMap<(&'static str,&'static str,u32),Vec<u8>> {
+    let dir = CONFIG.get_mut().shaders.dir.clone();
+    let dir = dir.filter(|dir| dir.is_dir());
+    EMBEDDED_KERNELS.iter().map(|kernel| {
+        let spirv = dir.as_deref().and_then(|dir| read_override(dir,kernel)).unwrap_or_else(|| kernel.spirv.to_vec());
+        ((kernel.group,kernel.kernel,kernel.index),spirv)
+    }).collect()
+}
+
+// The override for a single kernel, used when the file changes while running.
+pub(crate) fn load_override(kernel: &EmbeddedKernel) -> Option<Vec<u8>> {
+    let dir = CONFIG.get_mut().shaders.dir.clone()?;
+    read_override(&dir,kernel)
+}
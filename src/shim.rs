@@ -0,0 +1,250 @@
+use std::ffi::{c_void, CString};
+use std::iter;
+use std::ptr::NonNull;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use retour::static_detour;
+use lazy_mut::LazyMut;
+use wgpu::{Backends, BufferUsages, Device};
+use wgpu_types::{BufferAddress, BufferDescriptor, Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages};
+use winapi::um::libloaderapi::{GetModuleHandleW, GetProcAddress, LoadLibraryW};
+use winapi::um::winnt::{PCSTR, PCWSTR};
+use windows::core::{IUnknown, Interface};
+use windows::Win32::Foundation::LUID;
+use windows::Win32::Graphics::Dxgi::{IDXGIAdapter, IDXGIAdapter3, DXGI_MEMORY_SEGMENT_GROUP_LOCAL, DXGI_QUERY_VIDEO_MEMORY_INFO};
+use windows::Win32::Graphics::Direct3D12::ID3D12Resource;
+use crate::config::CONFIG;
+use crate::adapter::AdapterCandidate;
+use crate::{adapter, crash, device, hook, logging, runtime, sigscan, INSTANCE};
+
+static ALREADY_INIT: LazyMut<bool> = LazyMut::new(|| false);
+const CREATE_GFX_DEVICE_HOOK: &str = "CreateGfxDevice";
+const CREATE_DEVICE_HOOK: &str = "D3D12CreateDevice";
+static_detour! {
+    static CreateGfxDevice: unsafe extern "win64" fn(i32,i32) -> *mut std::ffi::c_void;
+    static CreateDevice: unsafe extern "system" fn(usize,i32,usize,usize) -> i32;
+}
+#[unsafe(no_mangle)]
+unsafe extern "win64" fn CreateGfxDevice_hook(rend: i32,flags: i32) -> *mut std::ffi::c_void {
+    log::info!("CreateGfxDevice called with renderer {:#x}, flags {:#x}",rend,flags);
+    let tramp: unsafe extern "C" fn(i32,i32) -> *mut std::ffi::c_void = std::mem::transmute(CreateGfxDevice.trampoline().unwrap());
+    tramp(CONFIG.get_mut().forced_renderer, flags)
+}
+const DLL_PROCESS_DETACH: u32 = 0;
+const DLL_PROCESS_ATTACH: u32 = 1;
+static INIT_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+// Lets the init thread give up waiting for UnityPlayer.dll when we are unloaded first.
+static STOP_INIT: AtomicBool = AtomicBool::new(false);
+
+#[unsafe(no_mangle)]
+unsafe extern "system" fn DllMain(_: usize,reason: u32,reserved: usize) -> i32 {
+    match reason {
+        DLL_PROCESS_ATTACH => attach(),
+        // A non-null reserved means the process is exiting: other threads are already gone and the driver may be too,
+        // so only flush what we have.
+        DLL_PROCESS_DETACH if reserved != 0 => log::logger().flush(),
+        DLL_PROCESS_DETACH => detach(),
+        _ => {},
+    }
+    1
+}
+// We hold the loader lock here: nothing can be joined, and waiting on the GPU or dropping the device can deadlock,
+// so that's all left to TrueTrace_Shutdown. Only unhook so nothing calls into us once we're unmapped, then flush.
+unsafe fn detach() {
+    STOP_INIT.store(true,Ordering::SeqCst);
+    if device::status() != device::DEVICE_STATUS_UNINITIALISED {
+        log::warn!("Unloaded without TrueTrace_Shutdown, the device is not released");
+    }
+    remove_hooks();
+    log::logger().flush();
+}
+pub(crate) fn join_init_thread() {
+    STOP_INIT.store(true,Ordering::SeqCst);
+    let thread = INIT_THREAD.lock().unwrap().take();
+    if let Some(thread) = thread {
+        if thread.join().is_err() {
+            log::error!("Init thread panicked");
+        }
+    }
+}
+pub(crate) fn remove_hooks() {
+    hook::remove_all();
+    *ALREADY_INIT.get_mut() = false;
+}
+unsafe fn attach() {
+    if !*ALREADY_INIT.get_mut() {
+        STOP_INIT.store(false,Ordering::SeqCst);
+        let thread = std::thread::spawn(move || {
+            logging::init();
+            crash::install_hook();
+            let module = "UnityPlayer.dll"
+                .encode_utf16()
+                .chain(iter::once(0))
+                .collect::<Vec<u16>>();
+            let mut handle = GetModuleHandleW(PCWSTR::from(module.as_ptr() as _)) as usize;
+            while handle == 0 {
+                if STOP_INIT.load(Ordering::SeqCst) {
+                    return;
+                }
+                handle = GetModuleHandleW(PCWSTR::from(module.as_ptr() as _)) as usize;
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            log::debug!("UnityPlayer.dll loaded at {:#x}", handle);
+            match find_create_gfx_device(handle) {
+                Ok(func_addr) => init_stuff(func_addr),
+                Err(err) => log::error!("Could not locate CreateGfxDevice, leaving the renderer alone: {}",err),
+            }
+            let module_ = "d3d12.dll"
+                .encode_utf16()
+                .chain(iter::once(0))
+                .collect::<Vec<u16>>();
+            handle = GetModuleHandleW(PCWSTR::from(module_.as_ptr() as _)) as usize;
+            if handle == 0 {
+                handle = LoadLibraryW(PCWSTR::from(module_.as_ptr() as _)) as usize;
+            }
+            let create_addr = get_module_symbol_address("d3d12.dll", "D3D12CreateDevice").unwrap();
+            CreateDevice.initialize(std::mem::transmute(create_addr), |adapter_arg: usize, b, c, pDev: usize| {
+                log::info!("D3D12CreateDevice intercepted");
+                if let Err(err) = hook::disable(CREATE_DEVICE_HOOK) {
+                    log::warn!("{}",err);
+                }
+                match create_wgpu_device(adapter_arg) {
+                    Ok(raw) => {
+                        *(pDev as *mut *mut std::ffi::c_void) = raw;
+                        log::info!("Created wgpu device and handed its ID3D12Device to Unity");
+                        0
+                    },
+                    // Unity still gets a device, just not one TrueTrace can use.
+                    Err(err) => {
+                        log::error!("Could not create the wgpu device, letting Unity create its own: {}",err);
+                        CreateDevice.call(adapter_arg,b,c,pDev)
+                    }
+                }
+            }).unwrap();
+            hook::register_detour(CREATE_DEVICE_HOOK,&CreateDevice,create_addr);
+            hook::enable(CREATE_DEVICE_HOOK).unwrap();
+            for state in hook::states() {
+                log::info!("Hook installed: {}",state);
+            }
+        });
+        INIT_THREAD.lock().unwrap().replace(thread);
+        *ALREADY_INIT.get_mut() = true;
+    }
+}
+// Returns the ID3D12Device to hand to Unity. Nothing is installed unless every step succeeds.
+unsafe fn create_wgpu_device(adapter_arg: usize) -> Result<*mut c_void,String> {
+    let instance = device::create_instance(Backends::DX12);
+    let adapters = instance.enumerate_adapters(Backends::DX12);
+    let candidates = adapters.iter().map(|adapter| AdapterCandidate {
+        info: adapter.get_info(),
+        luid: wgpu_adapter_luid(adapter),
+    }).collect::<Vec<_>>();
+    let (index,reason) = adapter::select_adapter(&candidates,unsafe {requested_adapter_luid(adapter_arg)},&CONFIG.get_mut().adapter).ok_or("no usable D3D12 adapter")?;
+    let adapter = adapters.into_iter().nth(index).ok_or("selected adapter disappeared")?;
+    log::info!("Using adapter {:?} ({:?}): {}",candidates[index].info.name,candidates[index].info.device_type,reason);
+    let (dev,queue) = runtime::block_on(device::request_device(&adapter)).map_err(|err| err.to_string())?;
+    let raw = unsafe {dev.as_hal::<wgpu_hal::dx12::Api>()}.map(|hal| hal.raw_device().as_raw()).ok_or("wgpu device is not D3D12")?;
+    INSTANCE.get_mut().replace(instance);
+    device::install(adapter,dev,queue);
+    Ok(raw)
+}
+unsafe fn find_create_gfx_device(base: usize) -> Result<usize,String> {
+    let headers = unsafe {std::slice::from_raw_parts(base as *const u8,0x1000)};
+    let text = sigscan::find_section(headers,".text").ok_or("UnityPlayer.dll has no .text section")?;
+    let text_bytes = unsafe {std::slice::from_raw_parts((base + text.virtual_address) as *const u8,text.virtual_size)};
+    let configured = CONFIG.get_mut().hook.create_gfx_device_pattern.clone();
+    let mut signatures = Vec::new();
+    if let Some(pattern) = configured.as_deref() {
+        signatures.push(sigscan::Signature { unity_version: "hook.create_gfx_device_pattern", locator: sigscan::Locator::Pattern { pattern, offset: 0 } });
+    }
+    signatures.extend_from_slice(sigscan::CREATE_GFX_DEVICE_SIGNATURES);
+    let resolved = sigscan::resolve(text_bytes,text.virtual_address,&signatures)?;
+    log::info!("CreateGfxDevice found at UnityPlayer.dll+{:#x} (signature for {})",resolved.rva,resolved.unity_version);
+    log::info!("CreateGfxDevice starts with {}",sigscan::describe_bytes(text_bytes,resolved.rva - text.virtual_address,32));
+    Ok(base + resolved.rva)
+}
+fn luid_to_u64(luid: LUID) -> u64 {
+    ((luid.HighPart as u32 as u64) << 32) | luid.LowPart as u64
+}
+// `adapter_arg` is the IUnknown* Unity hands to D3D12CreateDevice; null means the default adapter.
+unsafe fn requested_adapter_luid(adapter_arg: usize) -> Option<u64> {
+    let raw = adapter_arg as *mut c_void;
+    let unknown = unsafe {IUnknown::from_raw_borrowed(&raw)}?;
+    let dxgi_adapter = unknown.cast::<IDXGIAdapter>().ok()?;
+    let desc = unsafe {dxgi_adapter.GetDesc()}.ok()?;
+    Some(luid_to_u64(desc.AdapterLuid))
+}
+fn wgpu_adapter_luid(adapter: &wgpu::Adapter) -> Option<u64> {
+    let hal = unsafe {adapter.as_hal::<wgpu_hal::dx12::Api>()}?;
+    let desc = unsafe {hal.raw_adapter().GetDesc()}.ok()?;
+    Some(luid_to_u64(desc.AdapterLuid))
+}
+// Local segment budget and usage as (budget, usage) in bytes.
+pub(crate) fn adapter_memory_info(adapter: &wgpu::Adapter) -> Option<(u64,u64)> {
+    let hal = unsafe {adapter.as_hal::<wgpu_hal::dx12::Api>()}?;
+    let adapter3 = hal.raw_adapter().cast::<IDXGIAdapter3>().ok()?;
+    let mut info = DXGI_QUERY_VIDEO_MEMORY_INFO::default();
+    unsafe {adapter3.QueryVideoMemoryInfo(0,DXGI_MEMORY_SEGMENT_GROUP_LOCAL,&mut info)}.ok()?;
+    Some((info.Budget,info.CurrentUsage))
+}
+// Unity calls D3D12CreateDevice again after a device reset; catch that one too so it gets our new device.
+pub(crate) fn rearm_create_device() {
+    match hook::enable(CREATE_DEVICE_HOOK) {
+        Ok(()) => log::info!("Re-armed the D3D12CreateDevice hook for device recovery"),
+        Err(err) => log::debug!("D3D12CreateDevice hook not re-armed: {}",err),
+    }
+}
+fn init_stuff(addr: usize) {
+    unsafe {CreateGfxDevice.initialize(std::mem::transmute(addr),|rend,flags| {
+        CreateGfxDevice_hook(rend,flags)
+    }).unwrap()};
+    hook::register_detour(CREATE_GFX_DEVICE_HOOK,&CreateGfxDevice,addr);
+    hook::enable(CREATE_GFX_DEVICE_HOOK).unwrap();
+}
+fn get_module_symbol_address(module: &str, symbol: &str) -> Option<usize> {
+    let module = module
+        .encode_utf16()
+        .chain(iter::once(0))
+        .collect::<Vec<u16>>();
+    let symbol = CString::new(symbol).unwrap();
+    unsafe {
+        let handle = GetModuleHandleW(PCWSTR::from(module.as_ptr() as _));
+        match NonNull::new(GetProcAddress(handle.cast(), PCSTR::from(symbol.as_ptr() as _))) {
+            Some(func) => Some(func.addr().get()),
+            None => None,
+        }
+    }
+}
+// Unity's resources are ID3D12Resources, which only a D3D12 device can wrap; the imports return None on any other backend.
+fn is_dx12(device: &Device) -> bool {
+    unsafe {device.as_hal::<wgpu_hal::dx12::Api>()}.is_some()
+}
+pub(crate) unsafe fn import_buffer(device: &Device,buf: *mut c_void,buf_size: usize,usage: BufferUsages) -> Option<wgpu::Buffer> {
+    if !is_dx12(device) {
+        return None;
+    }
+    let buf_wgpu_hal = unsafe {wgpu_hal::dx12::Device::buffer_from_raw(ID3D12Resource::from_raw(buf), buf_size as BufferAddress)};
+    Some(unsafe {device.create_buffer_from_hal::<wgpu_hal::dx12::Api>(buf_wgpu_hal,&BufferDescriptor {
+        label: None,
+        size: buf_size as BufferAddress,
+        usage,
+        mapped_at_creation: false,
+    })})
+}
+pub(crate) unsafe fn import_texture(device: &Device,tex: *mut c_void,size: Extent3d,format: TextureFormat,dimension: TextureDimension,mip_count: u32) -> Option<wgpu::Texture> {
+    if !is_dx12(device) {
+        return None;
+    }
+    let tex_wgpu_hal = unsafe {wgpu_hal::dx12::Device::texture_from_raw(ID3D12Resource::from_raw(tex), format,dimension,size,mip_count,1)};
+    Some(unsafe {device.create_texture_from_hal::<wgpu_hal::dx12::Api>(tex_wgpu_hal,&TextureDescriptor {
+        mip_level_count: mip_count,
+        label: None,
+        size,
+        sample_count: 1,
+        dimension,
+        format,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })})
+}
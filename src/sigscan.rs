@@ -0,0 +1,349 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bytes: Vec<Option<u8>>,
+}
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Pattern,String> {
+        let bytes = pattern.split_whitespace().map(|token| match token {
+            "?" | "??" => Ok(None),
+            _ => u8::from_str_radix(token,16).map(Some).map_err(|_| format!("invalid byte {:?} in pattern {:?}",token,pattern)),
+        }).collect::<Result<Vec<_>,_>>()?;
+        if bytes.is_empty() || bytes.iter().all(Option::is_none) {
+            return Err(format!("pattern {:?} has no concrete bytes",pattern));
+        }
+        Ok(Pattern { bytes })
+    }
+    pub fn matches(&self,bytes: &[u8]) -> bool {
+        bytes.len() >= self.bytes.len() && self.bytes.iter().zip(bytes).all(|(expected,actual)| expected.is_none_or(|b| b == *actual))
+    }
+    pub fn find(&self,haystack: &[u8]) -> Option<usize> {
+        self.find_all(haystack).next()
+    }
+    pub fn find_all<'a>(&'a self,haystack: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        haystack.windows(self.bytes.len()).enumerate().filter(move |(_,window)| self.matches(window)).map(|(i,_)| i)
+    }
+}
+impl fmt::Display for Pattern {
+    fn fmt(&self,f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens = self.bytes.iter().map(|b| match b {
+            Some(b) => format!("{:02X}",b),
+            None => String::from("??"),
+        }).collect::<Vec<_>>();
+        write!(f,"{}",tokens.join(" "))
+    }
+}
+// `len` bytes of `text` from `at`, formatted as a pattern. Logged whenever the hook resolves so a pattern for that
+// build can be taken from the log; wildcard any rel32/displacement bytes before adding it to the table.
+pub fn describe_bytes(text: &[u8],at: usize,len: usize) -> String {
+    let end = text.len().min(at.saturating_add(len));
+    Pattern { bytes: text.get(at..end).unwrap_or_default().iter().copied().map(Some).collect() }.to_string()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Locator<'a> {
+    // Byte pattern inside .text; `offset` is added to the match to reach the function start.
+    Pattern { pattern: &'a str, offset: isize },
+    // Fixed RVA from the module base, only trusted once the prologue check passes.
+    Rva(usize),
+}
+#[derive(Debug, Clone, Copy)]
+pub struct Signature<'a> {
+    pub unity_version: &'a str,
+    pub locator: Locator<'a>,
+}
+
+// Add an entry per UnityPlayer build; the first one that resolves to a plausible prologue wins.
+// Only the original build is known here, by RVA. Patterns for other builds come from the bytes logged on a successful
+// resolve, or can be supplied without a rebuild through `hook.create_gfx_device_pattern`, which is tried first.
+// A `Locator::Pattern` entry must come with its captured prologue: the "CreateGfxDevice starts with ..." log line of
+// that build, saved as src/sigscan_captures/<unity_version>.txt. The tests resolve every pattern against it.
+pub const CREATE_GFX_DEVICE_SIGNATURES: &[Signature<'static>] = &[
+    Signature {
+        unity_version: "original target build",
+        locator: Locator::Rva(0x8a7c00),
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub virtual_address: usize,
+    pub virtual_size: usize,
+}
+
+fn read_u16(bytes: &[u8],at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+fn read_u32(bytes: &[u8],at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+pub fn find_section(headers: &[u8],name: &str) -> Option<Section> {
+    if headers.get(0..2)? != b"MZ" {
+        return None;
+    }
+    let nt = read_u32(headers,0x3c)? as usize;
+    if headers.get(nt..nt + 4)? != b"PE\0\0" {
+        return None;
+    }
+    let file_header = nt + 4;
+    let section_count = read_u16(headers,file_header + 2)? as usize;
+    let optional_header_size = read_u16(headers,file_header + 16)? as usize;
+    let section_table = file_header + 20 + optional_header_size;
+    (0..section_count).map(|i| section_table + i * 40).find_map(|header| {
+        let raw_name = headers.get(header..header + 8)?;
+        let len = raw_name.iter().position(|&b| b == 0).unwrap_or(8);
+        if &raw_name[..len] != name.as_bytes() {
+            return None;
+        }
+        Some(Section {
+            virtual_address: read_u32(headers,header + 12)? as usize,
+            virtual_size: read_u32(headers,header + 8)? as usize,
+        })
+    })
+}
+
+const PROLOGUES: &[&str] = &[
+    "48 89 5C 24 ??",
+    "48 89 4C 24 ??",
+    "48 89 54 24 ??",
+    "4C 89 44 24 ??",
+    "48 89 74 24 ??",
+    "48 89 7C 24 ??",
+    "48 8B C4",
+    "4C 8B DC",
+    "48 83 EC ??",
+    "48 81 EC",
+    "40 53",
+    "40 55",
+    "40 56",
+    "40 57",
+    "41 54",
+    "41 55",
+    "41 56",
+    "41 57",
+    "55 48 8B EC",
+    "53 48 83 EC",
+    "56 48 83 EC",
+    "57 48 83 EC",
+];
+const PADDING: &[u8] = &[0xCC, 0xC3, 0x90];
+
+// `text` is the whole .text section and `at` the candidate function start within it.
+pub fn looks_like_prologue(text: &[u8],at: usize) -> bool {
+    let Some(code) = text.get(at..) else {
+        return false;
+    };
+    if at > 0 && !PADDING.contains(&text[at - 1]) {
+        return false;
+    }
+    PROLOGUES.iter().any(|p| Pattern::parse(p).unwrap().matches(code))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolved<'a> {
+    pub rva: usize,
+    pub unity_version: &'a str,
+}
+
+// Returns the RVA of the first signature that lands on a plausible function prologue.
+pub fn resolve<'a>(text: &[u8],text_rva: usize,signatures: &[Signature<'a>]) -> Result<Resolved<'a>,String> {
+    let mut rejected = Vec::new();
+    for signature in signatures {
+        let candidate = match signature.locator {
+            Locator::Pattern { pattern, offset } => {
+                let pattern = Pattern::parse(pattern)?;
+                let mut hits = pattern.find_all(text);
+                match (hits.next(), hits.next()) {
+                    (Some(hit), None) => hit.checked_add_signed(offset),
+                    (Some(_), Some(_)) => {
+                        rejected.push(format!("{}: pattern is not unique",signature.unity_version));
+                        continue;
+                    },
+                    (None, _) => None,
+                }
+            },
+            Locator::Rva(rva) => rva.checked_sub(text_rva),
+        };
+        match candidate {
+            Some(at) if at < text.len() && looks_like_prologue(text,at) => {
+                return Ok(Resolved {
+                    rva: text_rva + at,
+                    unity_version: signature.unity_version,
+                });
+            },
+            Some(at) if at < text.len() => rejected.push(format!("{}: no function prologue at {:#x}",signature.unity_version,text_rva + at)),
+            _ => rejected.push(format!("{}: not found",signature.unity_version)),
+        }
+    }
+    Err(format!("no signature matched ({})",rejected.join("; ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Minimal PE32+ image: DOS header, NT headers with an empty optional header and the given sections.
+    fn pe(sections: &[(&str,u32,u32)]) -> Vec<u8> {
+        let nt = 0x80;
+        let mut image = vec![0u8; nt + 24 + sections.len() * 40];
+        image[0..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&(nt as u32).to_le_bytes());
+        image[nt..nt + 4].copy_from_slice(b"PE\0\0");
+        image[nt + 6..nt + 8].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        for (i,(name,virtual_address,virtual_size)) in sections.iter().enumerate() {
+            let header = nt + 24 + i * 40;
+            image[header..header + name.len()].copy_from_slice(name.as_bytes());
+            image[header + 8..header + 12].copy_from_slice(&virtual_size.to_le_bytes());
+            image[header + 12..header + 16].copy_from_slice(&virtual_address.to_le_bytes());
+        }
+        image
+    }
+
+    // .text filled with int3 padding, with `code` placed at each offset.
+    fn text(len: usize,functions: &[(usize,&[u8])]) -> Vec<u8> {
+        let mut text = vec![0xCC; len];
+        for (at,code) in functions {
+            text[*at..*at + code.len()].copy_from_slice(code);
+        }
+        text
+    }
+
+    const FUNCTION: &[u8] = &[0x48, 0x89, 0x5C, 0x24, 0x08, 0x57, 0x48, 0x83, 0xEC, 0x20, 0xE8, 0x11, 0x22, 0x33, 0x44];
+
+    #[test]
+    fn parses_and_prints_patterns() {
+        let pattern = Pattern::parse("48 8b ?? 24 ?").unwrap();
+        assert_eq!(pattern.to_string(),"48 8B ?? 24 ??");
+        assert!(Pattern::parse("").is_err());
+        assert!(Pattern::parse("?? ??").is_err());
+        assert!(Pattern::parse("48 8G").is_err());
+        assert!(Pattern::parse("480").is_err());
+    }
+
+    #[test]
+    fn matches_with_wildcards() {
+        let pattern = Pattern::parse("E8 ?? ?? ?? ?? 90").unwrap();
+        assert!(pattern.matches(&[0xE8, 1, 2, 3, 4, 0x90, 0xFF]));
+        assert!(!pattern.matches(&[0xE8, 1, 2, 3, 4, 0x91]));
+        assert!(!pattern.matches(&[0xE8, 1, 2, 3, 4]));
+        let haystack = [0x90, 0xE8, 0, 0, 0, 0, 0x90, 0xE8, 9, 9, 9, 9, 0x90];
+        assert_eq!(pattern.find(&haystack),Some(1));
+        assert_eq!(pattern.find_all(&haystack).collect::<Vec<_>>(),vec![1, 7]);
+        assert_eq!(pattern.find(&haystack[..5]),None);
+    }
+
+    #[test]
+    fn describes_bytes_as_a_pattern() {
+        assert_eq!(describe_bytes(FUNCTION,0,4),"48 89 5C 24");
+        assert_eq!(describe_bytes(FUNCTION,13,8),"33 44");
+        assert_eq!(describe_bytes(FUNCTION,100,8),"");
+    }
+
+    #[test]
+    fn finds_sections() {
+        let image = pe(&[(".text",0x1000,0x5000), (".rdata",0x6000,0x800)]);
+        assert_eq!(find_section(&image,".text"),Some(Section { virtual_address: 0x1000, virtual_size: 0x5000 }));
+        assert_eq!(find_section(&image,".rdata"),Some(Section { virtual_address: 0x6000, virtual_size: 0x800 }));
+        assert_eq!(find_section(&image,".data"),None);
+        assert_eq!(find_section(&image,".tex"),None);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let image = pe(&[(".text",0x1000,0x5000)]);
+        let mut no_mz = image.clone();
+        no_mz[0] = 0;
+        assert_eq!(find_section(&no_mz,".text"),None);
+        let mut no_pe = image.clone();
+        no_pe[0x80] = 0;
+        assert_eq!(find_section(&no_pe,".text"),None);
+        assert_eq!(find_section(&image[..image.len() - 40 + 14],".text"),None);
+        assert_eq!(find_section(&image[..0x40],".text"),None);
+    }
+
+    #[test]
+    fn checks_prologues() {
+        let text = text(0x40,&[(0x10,FUNCTION)]);
+        assert!(looks_like_prologue(&text,0x10));
+        // Mid-function: the previous byte isn't padding.
+        assert!(!looks_like_prologue(&text,0x11));
+        // Padding, not code.
+        assert!(!looks_like_prologue(&text,0x30));
+        assert!(!looks_like_prologue(&text,0x40));
+        assert!(looks_like_prologue(FUNCTION,0));
+    }
+
+    #[test]
+    fn resolves_patterns_and_rvas() {
+        let text = text(0x100,&[(0x20,FUNCTION), (0x80,&[0x40, 0x53, 0x48, 0x83, 0xEC, 0x20])]);
+        let by_pattern = [Signature { unity_version: "a", locator: Locator::Pattern { pattern: "48 89 5C 24 ?? 57", offset: 0 } }];
+        assert_eq!(resolve(&text,0x1000,&by_pattern),Ok(Resolved { rva: 0x1020, unity_version: "a" }));
+        // The pattern may start inside the function.
+        let by_offset = [Signature { unity_version: "b", locator: Locator::Pattern { pattern: "E8 ?? ?? ?? ?? CC", offset: -10 } }];
+        assert_eq!(resolve(&text,0x1000,&by_offset),Ok(Resolved { rva: 0x1020, unity_version: "b" }));
+        let by_rva = [Signature { unity_version: "c", locator: Locator::Rva(0x1080) }];
+        assert_eq!(resolve(&text,0x1000,&by_rva),Ok(Resolved { rva: 0x1080, unity_version: "c" }));
+    }
+
+    #[test]
+    fn falls_through_rejected_signatures() {
+        let text = text(0x100,&[(0x20,FUNCTION), (0x80,FUNCTION)]);
+        let signatures = [
+            Signature { unity_version: "ambiguous", locator: Locator::Pattern { pattern: "48 89 5C 24 ?? 57", offset: 0 } },
+            Signature { unity_version: "missing", locator: Locator::Pattern { pattern: "DE AD BE EF", offset: 0 } },
+            Signature { unity_version: "mid-function", locator: Locator::Rva(0x1022) },
+            Signature { unity_version: "outside", locator: Locator::Rva(0x500) },
+            Signature { unity_version: "past the end", locator: Locator::Rva(0x2000) },
+            Signature { unity_version: "good", locator: Locator::Rva(0x1080) },
+        ];
+        assert_eq!(resolve(&text,0x1000,&signatures),Ok(Resolved { rva: 0x1080, unity_version: "good" }));
+        let err = resolve(&text,0x1000,&signatures[..5]).unwrap_err();
+        assert!(err.contains("ambiguous: pattern is not unique"),"{}",err);
+        assert!(err.contains("missing: not found"),"{}",err);
+        assert!(err.contains("mid-function: no function prologue at 0x1022"),"{}",err);
+        assert!(err.contains("outside: not found"),"{}",err);
+        assert!(err.contains("past the end: not found"),"{}",err);
+    }
+
+    #[test]
+    fn reports_bad_patterns() {
+        let signatures = [Signature { unity_version: "bad", locator: Locator::Pattern { pattern: "ZZ", offset: 0 } }];
+        assert!(resolve(&[0xCC; 16],0x1000,&signatures).is_err());
+    }
+
+    #[test]
+    fn builtin_patterns_resolve_against_captures() {
+        for signature in CREATE_GFX_DEVICE_SIGNATURES {
+            if !matches!(signature.locator,Locator::Pattern { .. }) {
+                continue;
+            }
+            let path = format!("{}/src/sigscan_captures/{}.txt",env!("CARGO_MANIFEST_DIR"),signature.unity_version);
+            let capture = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("no captured prologue for {}: {}: {}",signature.unity_version,path,e));
+            let bytes = capture.split_whitespace().map(|b| u8::from_str_radix(b,16).unwrap()).collect::<Vec<_>>();
+            let text = text(0x10 + bytes.len() + 0x10,&[(0x10,&bytes)]);
+            assert_eq!(resolve(&text,0x1000,&[*signature]),Ok(Resolved { rva: 0x1010, unity_version: signature.unity_version }));
+        }
+    }
+
+    #[test]
+    fn captures_round_trip_through_the_log_format() {
+        // What the hook logs is what gets saved as a capture.
+        let logged = describe_bytes(FUNCTION,0,32);
+        let bytes = logged.split_whitespace().map(|b| u8::from_str_radix(b,16).unwrap()).collect::<Vec<_>>();
+        assert_eq!(bytes,FUNCTION);
+    }
+
+    #[test]
+    fn builtin_signatures_parse() {
+        for signature in CREATE_GFX_DEVICE_SIGNATURES {
+            if let Locator::Pattern { pattern, .. } = signature.locator {
+                Pattern::parse(pattern).unwrap();
+            }
+        }
+        for prologue in PROLOGUES {
+            Pattern::parse(prologue).unwrap();
+        }
+    }
+}
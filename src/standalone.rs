@@ -0,0 +1,291 @@
+use std::ffi::{c_char, CStr};
+use std::sync::mpsc;
+use wgpu::{Backends, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, MapMode, Origin3d, PowerPreference, RequestAdapterOptions, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureDescriptor, TextureUsages};
+use crate::compute_shader_interop::{TextureDimension, UnityTextureEnum};
+use crate::memory::{self, Tracked};
+use crate::{device, runtime, DEVICE, INSTANCE};
+
+pub const BACKEND_DX12: u32 = 1;
+pub const BACKEND_VULKAN: u32 = 2;
+pub const BACKEND_GL: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StandaloneOptions {
+    pub backends: u32,
+    pub high_performance: bool,
+}
+impl Default for StandaloneOptions {
+    fn default() -> Self {
+        StandaloneOptions {
+            backends: BACKEND_DX12 | BACKEND_VULKAN | BACKEND_GL,
+            high_performance: true,
+        }
+    }
+}
+impl StandaloneOptions {
+    fn wgpu_backends(&self) -> Backends {
+        let mut backends = Backends::empty();
+        if self.backends & BACKEND_DX12 != 0 {
+            backends |= Backends::DX12;
+        }
+        if self.backends & BACKEND_VULKAN != 0 {
+            backends |= Backends::VULKAN;
+        }
+        if self.backends & BACKEND_GL != 0 {
+            backends |= Backends::GL;
+        }
+        backends
+    }
+}
+
+// There are no Unity resources here: bind ones from TrueTrace_CreateBuffer/CreateTexture with the SetOwned* calls.
+pub fn init_standalone(options: &StandaloneOptions) -> Result<(),String> {
+    crate::logging::init();
+    crate::crash::install_hook();
+    let instance = device::create_instance(options.wgpu_backends());
+    let adapter = runtime::block_on(instance.request_adapter(&RequestAdapterOptions {
+        power_preference: if options.high_performance { PowerPreference::HighPerformance } else { PowerPreference::LowPower },
+        force_fallback_adapter: false,
+        compatible_surface: None,
+    })).map_err(|e| e.to_string())?;
+    let info = adapter.get_info();
+    log::info!("Standalone adapter: {} ({:?})",info.name,info.backend);
+    let (dev,queue) = runtime::block_on(device::request_device(&adapter)).map_err(|e| e.to_string())?;
+    INSTANCE.get_mut().replace(instance);
+    device::install(adapter,dev,queue);
+    crate::render::init();
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_InitStandalone(options: *const StandaloneOptions) -> bool {
+    let options = if options.is_null() {
+        StandaloneOptions::default()
+    } else {
+        unsafe {*options}
+    };
+    match init_standalone(&options) {
+        Ok(()) => true,
+        Err(err) => {
+            log::error!("Standalone init failed: {}",err);
+            false
+        }
+    }
+}
+
+// Resources created and owned by wgpu. A standalone device has no Unity resources to import, and only a D3D12 device
+// can import them at all, so these are what ComputeShader_SetOwnedBuffer/SetOwnedTexture bind instead.
+// A bound resource stays alive after its handle is released, until it's rebound or the shader goes away.
+pub struct OwnedBuffer {
+    pub(crate) buffer: wgpu::Buffer,
+    pub(crate) generation: u64,
+    pub(crate) memory: Tracked,
+}
+pub struct OwnedTexture {
+    pub(crate) texture: wgpu::Texture,
+    pub(crate) generation: u64,
+    pub(crate) memory: Tracked,
+}
+
+fn label_of(label: *const c_char) -> String {
+    if label.is_null() {
+        String::new()
+    } else {
+        unsafe {CStr::from_ptr(label)}.to_string_lossy().into_owned()
+    }
+}
+fn aligned(value: usize) -> bool {
+    value % wgpu::COPY_BUFFER_ALIGNMENT as usize == 0
+}
+
+// `contents` is null for a zeroed buffer, otherwise `size` bytes. The size is padded to a multiple of 4.
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_CreateBuffer(label: *const c_char,size: usize,contents: *const u8) -> *mut OwnedBuffer {
+    let label = label_of(label);
+    if !device::is_ready() {
+        log::error!("Can't create buffer {} while the device status is {}",label,device::status());
+        return std::ptr::null_mut();
+    }
+    let padded = size.max(1).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT as usize);
+    let binding = DEVICE.get_mut();
+    let (wgpu_device,queue) = binding.as_ref().unwrap();
+    let buffer = wgpu_device.create_buffer(&BufferDescriptor {
+        label: Some(label.as_str()),
+        size: padded as u64,
+        usage: BufferUsages::STORAGE | BufferUsages::UNIFORM | BufferUsages::INDIRECT | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    if !contents.is_null() && size > 0 {
+        let mut data = unsafe {std::slice::from_raw_parts(contents,size)}.to_vec();
+        data.resize(padded,0);
+        queue.write_buffer(&buffer,0,&data);
+    }
+    let memory = memory::track_created(padded as u64,memory::categorize(&label,false));
+    Box::into_raw(Box::new(OwnedBuffer { buffer, generation: device::generation(), memory }))
+}
+
+// `offset` and `len` must be multiples of 4.
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_WriteBuffer(buffer: *mut OwnedBuffer,offset: usize,data: *const u8,len: usize) -> bool {
+    let Some(owned) = (unsafe {buffer.as_ref()}) else {
+        return false;
+    };
+    if owned.generation != device::generation() || !device::is_ready() {
+        log::warn!("WriteBuffer on a buffer from a lost device, recreate it");
+        return false;
+    }
+    if !aligned(offset) || !aligned(len) || offset.checked_add(len).is_none_or(|end| end as u64 > owned.buffer.size()) {
+        log::error!("WriteBuffer of {} bytes at {} is unaligned or outside the {} byte buffer",len,offset,owned.buffer.size());
+        return false;
+    }
+    let data = unsafe {std::slice::from_raw_parts(data,len)};
+    DEVICE.get_mut().as_ref().unwrap().1.write_buffer(&owned.buffer,offset as u64,data);
+    true
+}
+
+// Copies `len` bytes from `offset` into `out` once all submitted work is done. Blocks; meant for tests and tools.
+// `offset` and `len` must be multiples of 4.
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_ReadBuffer(buffer: *mut OwnedBuffer,offset: usize,out: *mut u8,len: usize) -> bool {
+    let Some(owned) = (unsafe {buffer.as_ref()}) else {
+        return false;
+    };
+    if owned.generation != device::generation() || !device::is_ready() {
+        log::warn!("ReadBuffer on a buffer from a lost device, recreate it");
+        return false;
+    }
+    if len == 0 || !aligned(offset) || !aligned(len) || offset.checked_add(len).is_none_or(|end| end as u64 > owned.buffer.size()) {
+        log::error!("ReadBuffer of {} bytes at {} is empty, unaligned or outside the {} byte buffer",len,offset,owned.buffer.size());
+        return false;
+    }
+    // The DEVICE lock is dropped before waiting, so the poller and the device-lost callback aren't blocked on us.
+    let (wgpu_device,staging) = {
+        let binding = DEVICE.get_mut();
+        let (wgpu_device,queue) = binding.as_ref().unwrap();
+        let staging = wgpu_device.create_buffer(&BufferDescriptor {
+            label: Some("TrueTrace readback"),
+            size: len as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = wgpu_device.create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.copy_buffer_to_buffer(&owned.buffer,offset as u64,&staging,0,len as u64);
+        queue.submit(Some(encoder.finish()));
+        (wgpu_device.clone(),staging)
+    };
+    let (sender,receiver) = mpsc::channel();
+    staging.slice(..).map_async(MapMode::Read,move |result| {
+        let _ = sender.send(result);
+    });
+    if let Err(err) = wgpu_device.poll(wgpu::PollType::Wait) {
+        log::error!("ReadBuffer: waiting for the device failed: {}",err);
+        return false;
+    }
+    match receiver.recv() {
+        Ok(Ok(())) => {
+            let out = unsafe {std::slice::from_raw_parts_mut(out,len)};
+            out.copy_from_slice(&staging.slice(..).get_mapped_range());
+            staging.unmap();
+            true
+        },
+        Ok(Err(err)) => {
+            log::error!("ReadBuffer: mapping failed: {}",err);
+            false
+        },
+        Err(_) => {
+            log::error!("ReadBuffer: the mapping was dropped before completing");
+            false
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_ReleaseBuffer(buffer: *mut OwnedBuffer) {
+    if !buffer.is_null() {
+        drop(unsafe {Box::from_raw(buffer)});
+    }
+}
+
+// `format` and `dimension` are Unity's GraphicsFormat and TextureDimension, as for ComputeShader_SetTexture.
+// `depth` is the depth of a 3D texture or the layer count of a 2D one.
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_CreateTexture(label: *const c_char,width: u32,height: u32,depth: u32,format: u32,dimension: i32,mip_count: u32) -> *mut OwnedTexture {
+    let label = label_of(label);
+    if !device::is_ready() {
+        log::error!("Can't create texture {} while the device status is {}",label,device::status());
+        return std::ptr::null_mut();
+    }
+    let unity_tex_fmt: UnityTextureEnum = unsafe {std::mem::transmute(format)};
+    let unity_dim: TextureDimension = unsafe {std::mem::transmute(dimension)};
+    let (format,dimension) = match (wgpu::TextureFormat::try_from(unity_tex_fmt),wgpu::TextureDimension::try_from(unity_dim)) {
+        (Ok(format),Ok(dimension)) => (format,dimension),
+        (Err(err),_) | (_,Err(err)) => {
+            log::error!("Can't create texture {} ({:?}, {:?}): {}",label,unity_tex_fmt,unity_dim,err);
+            return std::ptr::null_mut();
+        }
+    };
+    let binding = DEVICE.get_mut();
+    let (wgpu_device,_) = binding.as_ref().unwrap();
+    // Not every format can be a storage texture; ask only for what the format guarantees.
+    let wanted = TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST;
+    let usage = wanted & format.guaranteed_format_features(wgpu_device.features()).allowed_usages;
+    let texture = wgpu_device.create_texture(&TextureDescriptor {
+        label: Some(label.as_str()),
+        size: Extent3d { width, height, depth_or_array_layers: depth.max(1) },
+        mip_level_count: mip_count.max(1),
+        sample_count: 1,
+        dimension,
+        format,
+        usage,
+        view_formats: &[],
+    });
+    let memory = memory::track_created(crate::compute_shader_interop::texture_bytes(&texture),memory::categorize(&label,true));
+    Box::into_raw(Box::new(OwnedTexture { texture, generation: device::generation(), memory }))
+}
+
+// Uploads a whole mip level, rows tightly packed, `len` bytes in all.
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_WriteTexture(texture: *mut OwnedTexture,mip: u32,data: *const u8,len: usize) -> bool {
+    let Some(owned) = (unsafe {texture.as_ref()}) else {
+        return false;
+    };
+    if owned.generation != device::generation() || !device::is_ready() {
+        log::warn!("WriteTexture on a texture from a lost device, recreate it");
+        return false;
+    }
+    let texture = &owned.texture;
+    if mip >= texture.mip_level_count() {
+        log::error!("WriteTexture to mip {} of a texture with {} mips",mip,texture.mip_level_count());
+        return false;
+    }
+    let size = texture.size().mip_level_size(mip,texture.dimension()).physical_size(texture.format());
+    let (block_width,block_height) = texture.format().block_dimensions();
+    let block_size = texture.format().block_copy_size(None).unwrap_or(4);
+    let bytes_per_row = size.width / block_width * block_size;
+    let rows = size.height / block_height;
+    let expected = bytes_per_row as usize * rows as usize * size.depth_or_array_layers as usize;
+    if len != expected {
+        log::error!("WriteTexture of mip {} expects {} bytes, got {}",mip,expected,len);
+        return false;
+    }
+    let data = unsafe {std::slice::from_raw_parts(data,len)};
+    DEVICE.get_mut().as_ref().unwrap().1.write_texture(TexelCopyTextureInfo {
+        texture,
+        mip_level: mip,
+        origin: Origin3d::ZERO,
+        aspect: TextureAspect::All,
+    },data,TexelCopyBufferLayout {
+        offset: 0,
+        bytes_per_row: Some(bytes_per_row),
+        rows_per_image: Some(rows),
+    },texture.size().mip_level_size(mip,texture.dimension()));
+    true
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn TrueTrace_ReleaseTexture(texture: *mut OwnedTexture) {
+    if !texture.is_null() {
+        drop(unsafe {Box::from_raw(texture)});
+    }
+}
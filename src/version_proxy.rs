@@ -0,0 +1,108 @@
+use std::iter;
+use std::sync::OnceLock;
+use winapi::um::libloaderapi::{GetProcAddress, LoadLibraryW};
+use winapi::um::sysinfoapi::GetSystemDirectoryW;
+
+// We are loaded as version.dll, so everything the real one exports has to be passed through to System32's copy.
+fn real_version_dll() -> usize {
+    static MODULE: OnceLock<usize> = OnceLock::new();
+    *MODULE.get_or_init(|| unsafe {
+        let mut dir = [0u16; 260];
+        let len = GetSystemDirectoryW(dir.as_mut_ptr(),dir.len() as u32) as usize;
+        let path = dir[..len.min(dir.len())].iter().copied()
+            .chain("\\version.dll".encode_utf16())
+            .chain(iter::once(0))
+            .collect::<Vec<u16>>();
+        let module = LoadLibraryW(path.as_ptr()) as usize;
+        if module == 0 {
+            log::error!("Could not load the system version.dll, version queries will fail");
+        }
+        module
+    })
+}
+fn resolve(name: &str) -> Option<usize> {
+    let module = real_version_dll();
+    if module == 0 {
+        return None;
+    }
+    let symbol = format!("{}\0",name);
+    let addr = unsafe {GetProcAddress(module as _,symbol.as_ptr() as _)} as usize;
+    if addr == 0 {
+        log::error!("System version.dll has no export {}",name);
+    }
+    (addr != 0).then_some(addr)
+}
+
+// Each entry becomes a no_mangle export that resolves its real counterpart once and calls through,
+// returning the fallback if the system DLL or symbol is missing.
+macro_rules! forward_exports {
+    ($($name:ident($($arg:ident: $ty:ty),*) -> $ret:ty = $fallback:expr;)*) => {
+        pub const EXPORT_NAMES: &[&str] = &[$(stringify!($name)),*];
+        $(
+            #[unsafe(no_mangle)]
+            unsafe extern "system" fn $name($($arg: $ty),*) -> $ret {
+                static REAL: OnceLock<Option<usize>> = OnceLock::new();
+                match *REAL.get_or_init(|| resolve(stringify!($name))) {
+                    Some(addr) => {
+                        let real: unsafe extern "system" fn($($ty),*) -> $ret = unsafe {std::mem::transmute(addr)};
+                        unsafe {real($($arg),*)}
+                    },
+                    None => $fallback,
+                }
+            }
+        )*
+    };
+}
+
+// Pointers and handles are usize, DWORD/UINT are u32 and BOOL is i32.
+forward_exports! {
+    GetFileVersionInfoA(filename: usize,handle: u32,len: u32,data: usize) -> i32 = 0;
+    GetFileVersionInfoByHandle(flags: u32,file: usize,data: usize,len: usize) -> i32 = 0;
+    GetFileVersionInfoExA(flags: u32,filename: usize,handle: u32,len: u32,data: usize) -> i32 = 0;
+    GetFileVersionInfoExW(flags: u32,filename: usize,handle: u32,len: u32,data: usize) -> i32 = 0;
+    GetFileVersionInfoSizeA(filename: usize,handle: usize) -> u32 = 0;
+    GetFileVersionInfoSizeExA(flags: u32,filename: usize,handle: usize) -> u32 = 0;
+    GetFileVersionInfoSizeExW(flags: u32,filename: usize,handle: usize) -> u32 = 0;
+    GetFileVersionInfoSizeW(filename: usize,handle: usize) -> u32 = 0;
+    GetFileVersionInfoW(filename: usize,handle: u32,len: u32,data: usize) -> i32 = 0;
+    VerFindFileA(flags: u32,filename: usize,win_dir: usize,app_dir: usize,cur_dir: usize,cur_dir_len: usize,dest_dir: usize,dest_dir_len: usize) -> u32 = 0;
+    VerFindFileW(flags: u32,filename: usize,win_dir: usize,app_dir: usize,cur_dir: usize,cur_dir_len: usize,dest_dir: usize,dest_dir_len: usize) -> u32 = 0;
+    VerInstallFileA(flags: u32,src_filename: usize,dest_filename: usize,src_dir: usize,dest_dir: usize,cur_dir: usize,tmp_file: usize,tmp_file_len: usize) -> u32 = 0;
+    VerInstallFileW(flags: u32,src_filename: usize,dest_filename: usize,src_dir: usize,dest_dir: usize,cur_dir: usize,tmp_file: usize,tmp_file_len: usize) -> u32 = 0;
+    VerLanguageNameA(lang: u32,name: usize,len: u32) -> u32 = 0;
+    VerLanguageNameW(lang: u32,name: usize,len: u32) -> u32 = 0;
+    VerQueryValueA(block: usize,sub_block: usize,buffer: usize,len: usize) -> i32 = 0;
+    VerQueryValueW(block: usize,sub_block: usize,buffer: usize,len: usize) -> i32 = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn sorted<'a>(names: impl IntoIterator<Item = &'a str>) -> BTreeSet<&'a str> {
+        names.into_iter().collect()
+    }
+
+    fn read_u32(base: usize,rva: usize) -> u32 {
+        unsafe {((base + rva) as *const u32).read_unaligned()}
+    }
+
+    // Walks the loaded module's export directory (PE32+: data directory 0 at optional header + 112).
+    // A missing forward would break whatever imports it from us.
+    #[test]
+    fn matches_the_real_version_dll() {
+        let base = real_version_dll();
+        assert_ne!(base,0,"could not load the system version.dll");
+        let optional_header = read_u32(base,0x3c) as usize + 24;
+        let exports = read_u32(base,optional_header + 112) as usize;
+        let name_count = read_u32(base,exports + 24) as usize;
+        let names = read_u32(base,exports + 32) as usize;
+        let real = (0..name_count).map(|i| {
+            let name = unsafe {std::ffi::CStr::from_ptr((base + read_u32(base,names + i * 4) as usize) as *const _)};
+            name.to_str().unwrap()
+        }).collect::<BTreeSet<_>>();
+        assert_eq!(EXPORT_NAMES.len(),sorted(EXPORT_NAMES.iter().copied()).len(),"duplicate forward");
+        assert_eq!(sorted(EXPORT_NAMES.iter().copied()),real);
+    }
+}